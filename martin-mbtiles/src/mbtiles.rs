@@ -353,6 +353,43 @@ impl Mbtiles {
         Ok(None)
     }
 
+    /// Get a tile together with its hash, if the file stores tile hashes.
+    /// Only [`MbtType::FlatWithHash`] and [`MbtType::Normalized`] files have hashes.
+    pub async fn get_tile_and_hash<T>(
+        &self,
+        conn: &mut T,
+        mbt_type: MbtType,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> MbtResult<Option<(Vec<u8>, Option<String>)>>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        let sql = match mbt_type {
+            MbtType::Flat => {
+                "SELECT tile_data, NULL AS tile_hash FROM tiles WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?"
+            }
+            MbtType::FlatWithHash => {
+                "SELECT tile_data, tile_hash FROM tiles_with_hash WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?"
+            }
+            MbtType::Normalized => {
+                "SELECT images.tile_data, images.tile_id AS tile_hash FROM map JOIN images ON images.tile_id = map.tile_id WHERE map.zoom_level = ? AND map.tile_column = ? AND map.tile_row = ?"
+            }
+        };
+        let y = (1 << z) - 1 - y;
+        let row = query(sql)
+            .bind(z)
+            .bind(x)
+            .bind(y)
+            .fetch_optional(conn)
+            .await?;
+        Ok(row.and_then(|row| {
+            let tile_data: Option<Vec<u8>> = row.get(0);
+            tile_data.map(|v| (v, row.get(1)))
+        }))
+    }
+
     pub async fn detect_type<T>(&self, conn: &mut T) -> MbtResult<MbtType>
     where
         for<'e> &'e mut T: SqliteExecutor<'e>,
@@ -732,6 +769,42 @@ mod tests {
         assert!(matches!(res, Err(MbtError::InvalidDataFormat(_))));
     }
 
+    #[actix_rt::test]
+    async fn tile_and_hash() {
+        let (mut conn, mbt) = open("../tests/fixtures/files/world_cities.mbtiles").await;
+        let (tile, hash) = mbt
+            .get_tile_and_hash(&mut conn, MbtType::Flat, 0, 0, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!tile.is_empty());
+        assert_eq!(hash, None);
+
+        let (mut conn, mbt) = open("../tests/fixtures/files/zoomed_world_cities.mbtiles").await;
+        let (tile, hash) = mbt
+            .get_tile_and_hash(&mut conn, MbtType::FlatWithHash, 6, 10, 25)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!tile.is_empty());
+        assert!(hash.is_some());
+
+        let (mut conn, mbt) = open("../tests/fixtures/files/geography-class-jpg.mbtiles").await;
+        let (tile, hash) = mbt
+            .get_tile_and_hash(&mut conn, MbtType::Normalized, 0, 0, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!tile.is_empty());
+        assert!(hash.is_some());
+
+        let res = mbt
+            .get_tile_and_hash(&mut conn, MbtType::Normalized, 20, 0, 0)
+            .await
+            .unwrap();
+        assert!(res.is_none());
+    }
+
     #[actix_rt::test]
     async fn validate_valid_file() {
         let (mut conn, mbt) = open("../tests/fixtures/files/zoomed_world_cities.mbtiles").await;
@@ -3,7 +3,7 @@ use std::path::Path;
 use sqlx::{Pool, Sqlite, SqlitePool};
 
 use crate::errors::MbtResult;
-use crate::{Mbtiles, Metadata};
+use crate::{MbtType, Mbtiles, Metadata};
 
 #[derive(Clone, Debug)]
 pub struct MbtilesPool {
@@ -27,4 +27,22 @@ impl MbtilesPool {
         let mut conn = self.pool.acquire().await?;
         self.mbtiles.get_tile(&mut *conn, z, x, y).await
     }
+
+    pub async fn get_tile_and_hash(
+        &self,
+        mbt_type: MbtType,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> MbtResult<Option<(Vec<u8>, Option<String>)>> {
+        let mut conn = self.pool.acquire().await?;
+        self.mbtiles
+            .get_tile_and_hash(&mut *conn, mbt_type, z, x, y)
+            .await
+    }
+
+    pub async fn detect_type(&self) -> MbtResult<MbtType> {
+        let mut conn = self.pool.acquire().await?;
+        self.mbtiles.detect_type(&mut *conn).await
+    }
 }
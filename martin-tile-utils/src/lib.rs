@@ -64,7 +64,7 @@ impl Display for Format {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Encoding {
     /// Data is not compressed, but it can be
     Uncompressed = 0b0000_0000,
@@ -6,7 +6,7 @@ use crate::args::environment::Env;
 use crate::pg::{PgConfig, PgSslCerts, POOL_SIZE_DEFAULT};
 use crate::utils::OneOrMany;
 
-#[derive(clap::Args, Debug, PartialEq, Default)]
+#[derive(clap::Args, Debug, Clone, PartialEq, Default)]
 #[command(about, version)]
 pub struct PgArgs {
     /// Disable the automatic generation of bounds for spatial PG tables.
@@ -12,7 +12,7 @@ use crate::config::Config;
 use crate::file_config::FileConfigEnum;
 use crate::{Error, Result};
 
-#[derive(Parser, Debug, PartialEq, Default)]
+#[derive(Parser, Debug, Clone, PartialEq, Default)]
 #[command(about, version)]
 pub struct Args {
     #[command(flatten)]
@@ -44,6 +44,9 @@ pub struct MetaArgs {
     /// Export a directory with SVG files as a sprite source. Can be specified multiple times.
     #[arg(short, long)]
     pub sprite: Vec<PathBuf>,
+    /// Export a font file or a directory with font files as a font source (recursive). Can be specified multiple times.
+    #[arg(short, long)]
+    pub font: Vec<PathBuf>,
 }
 
 impl Args {
@@ -81,6 +84,10 @@ impl Args {
             config.sprites = FileConfigEnum::new(self.meta.sprite);
         }
 
+        if !self.meta.font.is_empty() {
+            config.fonts = FileConfigEnum::new(self.meta.font);
+        }
+
         cli_strings.check()
     }
 }
@@ -1,6 +1,9 @@
-use crate::srv::{SrvConfig, KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT};
+use std::path::PathBuf;
 
-#[derive(clap::Args, Debug, PartialEq, Default)]
+use crate::srv::{CorsConfig, SrvConfig, KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT};
+use crate::utils::{BoolOrObject, OneOrMany};
+
+#[derive(clap::Args, Debug, Clone, PartialEq, Default)]
 #[command(about, version)]
 pub struct SrvArgs {
     #[arg(help = format!("Connection keep alive timeout. [DEFAULT: {}]", KEEP_ALIVE_DEFAULT), short, long)]
@@ -10,6 +13,39 @@ pub struct SrvArgs {
     /// Number of web server workers
     #[arg(short = 'W', long)]
     pub workers: Option<usize>,
+    /// Size of the in-memory tile cache in megabytes. Tiles are not cached unless this is set, or configured in the config file.
+    #[arg(long)]
+    pub cache_size_mb: Option<u64>,
+    /// Path to a PEM file with the TLS certificate chain. HTTPS is used if both the certificate and the key are set.
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to a PEM file with the private key of the TLS certificate.
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+    /// Allowed CORS origin, e.g. `https://*.example.org`. Can be specified multiple times. [DEFAULT: *]
+    #[arg(long)]
+    pub cors_origin: Vec<String>,
+    /// Allowed CORS request method, e.g. `HEAD`. Can be specified multiple times. [DEFAULT: GET]
+    #[arg(long)]
+    pub cors_method: Vec<String>,
+    /// Allowed CORS request header. Can be specified multiple times. [DEFAULT: Authorization, Accept]
+    #[arg(long)]
+    pub cors_header: Vec<String>,
+    /// Number of seconds the browsers may cache the CORS preflight request results.
+    #[arg(long)]
+    pub cors_max_age: Option<usize>,
+    /// Allow the CORS requests with credentials, e.g. cookies. Requires listing the allowed origins with `--cors-origin`.
+    #[arg(long)]
+    pub cors_credentials: bool,
+    /// Disable CORS, so that the browsers block the requests from other origins.
+    #[arg(long)]
+    pub disable_cors: bool,
+    /// Serve all endpoints under this path prefix, e.g. `/tiles`.
+    #[arg(long)]
+    pub base_path: Option<String>,
+    /// The URL of this server as seen by the clients, e.g. `https://example.org/tiles`, used in the tile and style URLs.
+    #[arg(long)]
+    pub public_url: Option<String>,
 }
 
 impl SrvArgs {
@@ -24,5 +60,89 @@ impl SrvArgs {
         if self.workers.is_some() {
             srv_config.worker_processes = self.workers;
         }
+        if self.cache_size_mb.is_some() {
+            srv_config
+                .cache
+                .get_or_insert_with(Default::default)
+                .size_mb = self.cache_size_mb;
+        }
+        if self.tls_cert.is_some() {
+            srv_config.tls_cert = self.tls_cert;
+        }
+        if self.tls_key.is_some() {
+            srv_config.tls_key = self.tls_key;
+        }
+        if self.base_path.is_some() {
+            srv_config.base_path = self.base_path;
+        }
+        if self.public_url.is_some() {
+            srv_config.public_url = self.public_url;
+        }
+        if self.disable_cors {
+            srv_config.cors = Some(BoolOrObject::Bool(false));
+        } else if !self.cors_origin.is_empty()
+            || !self.cors_method.is_empty()
+            || !self.cors_header.is_empty()
+            || self.cors_max_age.is_some()
+            || self.cors_credentials
+        {
+            let mut cors = match srv_config.cors.take() {
+                Some(BoolOrObject::Object(cors)) => cors,
+                _ => CorsConfig::default(),
+            };
+            if let Some(origin) = OneOrMany::new_opt(self.cors_origin) {
+                cors.origin = Some(origin);
+            }
+            if !self.cors_method.is_empty() {
+                cors.methods = Some(self.cors_method);
+            }
+            if !self.cors_header.is_empty() {
+                cors.headers = Some(self.cors_header);
+            }
+            if self.cors_max_age.is_some() {
+                cors.max_age = self.cors_max_age;
+            }
+            if self.cors_credentials {
+                cors.credentials = Some(true);
+            }
+            srv_config.cors = Some(BoolOrObject::Object(cors));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_cors() {
+        let mut srv_config = SrvConfig::default();
+        SrvArgs {
+            cors_method: vec!["GET".to_string(), "HEAD".to_string()],
+            cors_max_age: Some(3600),
+            ..Default::default()
+        }
+        .merge_into_config(&mut srv_config);
+        let expected = CorsConfig {
+            methods: Some(vec!["GET".to_string(), "HEAD".to_string()]),
+            max_age: Some(3600),
+            ..Default::default()
+        };
+        assert_eq!(srv_config.cors, Some(BoolOrObject::Object(expected)));
+
+        // Credentials are validated like the ones of the config file
+        let mut srv_config = SrvConfig::default();
+        SrvArgs {
+            cors_credentials: true,
+            ..Default::default()
+        }
+        .merge_into_config(&mut srv_config);
+        assert!(CorsConfig::resolve(srv_config.cors.as_ref()).is_err());
+        SrvArgs {
+            cors_origin: vec!["https://example.org".to_string()],
+            ..Default::default()
+        }
+        .merge_into_config(&mut srv_config);
+        assert!(CorsConfig::resolve(srv_config.cors.as_ref()).is_ok());
     }
 }
@@ -1,23 +1,19 @@
 use std::ffi::OsStr;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::Write;
+use std::sync::Arc;
 
 use actix_web::dev::Server;
 use clap::Parser;
 use log::{error, info, log_enabled};
 use martin::args::{Args, OsEnv};
-use martin::srv::{new_server, RESERVED_KEYWORDS};
-use martin::Error::ConfigWriteError;
+use martin::srv::{new_server, SharedSources, SourcesLoader, RESERVED_KEYWORDS};
 use martin::{read_config, Config, IdResolver, Result};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-async fn start(args: Args) -> Result<Server> {
-    info!("Starting Martin v{VERSION}");
-
+/// Read the config file if given, and apply the command line arguments to it
+fn load_config(args: Args) -> Result<Config> {
     let env = OsEnv::default();
-    let save_config = args.meta.save_config.clone();
     let mut config = if let Some(ref cfg_filename) = args.meta.config {
         info!("Using {}", cfg_filename.display());
         read_config(cfg_filename, &env)?
@@ -28,11 +24,22 @@ async fn start(args: Args) -> Result<Server> {
 
     args.merge_into_config(&mut config, &env)?;
     config.finalize()?;
+    Ok(config)
+}
+
+async fn start(args: Args) -> Result<Server> {
+    info!("Starting Martin v{VERSION}");
+
+    let save_config = args.meta.save_config.clone();
+    let mut reload_args = args.clone();
+    let mut config = load_config(args)?;
     let sources = config.resolve(IdResolver::new(RESERVED_KEYWORDS)).await?;
 
+    // Sources changed with the admin API are saved to the same file
+    let mut config_file = None;
     if let Some(file_name) = save_config {
-        let yaml = serde_yaml::to_string(&config).expect("Unable to serialize config");
         if file_name.as_os_str() == OsStr::new("-") {
+            let yaml = serde_yaml::to_string(&config).expect("Unable to serialize config");
             info!("Current system configuration:");
             println!("\n\n{yaml}\n");
         } else {
@@ -40,15 +47,32 @@ async fn start(args: Args) -> Result<Server> {
                 "Saving config to {}, use --config to load it",
                 file_name.display()
             );
-            File::create(file_name.clone())
-                .map_err(|e| ConfigWriteError(e, file_name.clone()))?
-                .write_all(yaml.as_bytes())
-                .map_err(|e| ConfigWriteError(e, file_name.clone()))?;
+            config.save_to_file(&file_name)?;
+            config_file = Some(file_name);
         }
     } else {
         info!("Use --save-config to save or print Martin configuration.");
     }
 
+    // The sources are reloaded from the saved file, which also has the sources changed
+    // with the admin API, instead of the original configuration file and arguments
+    if let Some(file_name) = &config_file {
+        reload_args.meta.config = Some(file_name.clone());
+        reload_args.meta.connection.clear();
+        reload_args.meta.sprite.clear();
+        reload_args.meta.font.clear();
+    }
+    // Server settings like the listening address are not reloaded, only the sources
+    let loader: SourcesLoader = Arc::new(move || {
+        let args = reload_args.clone();
+        Box::pin(async move {
+            load_config(args)?
+                .resolve(IdResolver::new(RESERVED_KEYWORDS))
+                .await
+        })
+    });
+
+    let sources = SharedSources::new(sources, Some(loader)).save_config(config_file);
     let (server, listen_addresses) = new_server(config.srv, sources)?;
     info!("Martin has been started on {listen_addresses}.");
     info!("Use http://{listen_addresses}/catalog to get the list of available sources.");
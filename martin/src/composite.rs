@@ -0,0 +1,342 @@
+use std::collections::{BTreeSet, HashMap};
+
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use itertools::Itertools as _;
+use log::{info, warn};
+use martin_tile_utils::{Encoding, Format, TileInfo};
+use serde::{Deserialize, Serialize};
+use tilejson::TileJSON;
+
+use crate::source::{
+    Source, SourceKind, SourceMember, SourceMembers, Sources, TileData, UrlQuery, Xyz,
+};
+use crate::srv::{merge_tilejson, merge_tiles, RateLimit};
+use crate::utils::{most_restrictive_cache_control, recompress, rename_layers, IdResolver, Result};
+use crate::Error::CompositeError;
+
+/// Composite sources by their IDs, each a list of the sources whose layers it combines, in order
+pub type CompositeConfig = HashMap<String, Vec<CompositeMemberSrc>>;
+
+/// A member of a composite source, either a source ID, or a source ID with its layer name
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompositeMemberSrc {
+    Id(String),
+    Obj(CompositeMember),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompositeMember {
+    /// ID of the source to get the layer from
+    pub source: String,
+    /// Rename the layer of the source's tiles, e.g. to avoid two layers with the same name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+}
+
+impl SourceMember for CompositeMember {
+    fn source_id(&self) -> &str {
+        &self.source
+    }
+}
+
+impl SourceMember for CompositeMemberSrc {
+    fn source_id(&self) -> &str {
+        match self {
+            Self::Id(source) => source,
+            Self::Obj(member) => &member.source,
+        }
+    }
+}
+
+impl CompositeMemberSrc {
+    #[must_use]
+    pub fn into_member(self) -> CompositeMember {
+        match self {
+            Self::Id(source) => CompositeMember {
+                source,
+                ..Default::default()
+            },
+            Self::Obj(member) => member,
+        }
+    }
+}
+
+/// Create the composite sources from the sources they refer to, and add them to the sources
+pub fn resolve_composites(
+    config: Option<&CompositeConfig>,
+    sources: &mut Sources,
+    idr: &IdResolver,
+) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    for (id, members) in config.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        let members = members
+            .iter()
+            .cloned()
+            .map(CompositeMemberSrc::into_member)
+            .collect();
+        let (id, members) =
+            sources.resolve_members(idr, "composite", id, members, CompositeError)?;
+        let source = CompositeSource::new(id.clone(), members)?;
+        info!("Configured composite source {id}");
+        sources.insert(id, Box::new(source));
+    }
+    Ok(())
+}
+
+/// A vector tile source combining the layers of its member sources into one tile
+#[derive(Debug, Clone)]
+pub struct CompositeSource {
+    id: String,
+    members: SourceMembers<CompositeMember>,
+    tilejson: TileJSON,
+    info: TileInfo,
+    layers: Option<Vec<String>>,
+}
+
+impl CompositeSource {
+    pub fn new(id: String, members: SourceMembers<CompositeMember>) -> Result<Self> {
+        if members.is_empty() {
+            return Err(CompositeError(id, "no sources are configured".to_string()));
+        }
+        for (member, src) in &members {
+            let info = src.get_tile_info();
+            if info.format != Format::Mvt {
+                return Err(CompositeError(
+                    id,
+                    format!(
+                        "only vector tiles can be combined, but {} has {info}",
+                        member.source
+                    ),
+                ));
+            }
+            if member.layer.is_some() && src.get_layers().map_or(false, |v| v.len() > 1) {
+                return Err(CompositeError(
+                    id,
+                    format!(
+                        "the layer of {} cannot be renamed because it has several layers",
+                        member.source
+                    ),
+                ));
+            }
+        }
+
+        // Tiles are concatenated as is if they are all uncompressed or all gzip-compressed,
+        // and their layers are not renamed. Otherwise, they are decoded first.
+        let mut encodings = members.iter().map(|(_, s)| s.get_tile_info().encoding);
+        let first = encodings.next().unwrap_or(Encoding::Uncompressed);
+        let is_renamed = members.iter().any(|(m, _)| m.layer.is_some());
+        let encoding = if encodings.all(|v| v == first)
+            && matches!(first, Encoding::Uncompressed | Encoding::Gzip)
+            && !is_renamed
+        {
+            first
+        } else {
+            Encoding::Uncompressed
+        };
+
+        let sources = members.iter().map(|(_, s)| s.as_ref()).collect();
+        let mut tilejson = merge_tilejson(sources, String::new());
+        tilejson.tiles = vec![];
+        let vector_layers: Vec<_> = members
+            .iter()
+            .flat_map(|(member, src)| {
+                let layers = src.get_tilejson().vector_layers.unwrap_or_default();
+                layers.into_iter().map(|mut layer| {
+                    if let Some(name) = &member.layer {
+                        layer.id.clone_from(name);
+                    }
+                    layer
+                })
+            })
+            .collect();
+        tilejson.vector_layers = (!vector_layers.is_empty()).then_some(vector_layers);
+
+        let layers = members
+            .iter()
+            .map(|(member, src)| match &member.layer {
+                Some(name) => Some(vec![name.clone()]),
+                None => src.get_layers().map(<[String]>::to_vec),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|v| v.concat());
+        if let Some(layers) = &layers {
+            let mut names = BTreeSet::new();
+            for name in layers.iter().filter(|v| !names.insert(*v)) {
+                warn!("Composite source {id} has several {name} layers, use the `layer` option to rename them");
+            }
+        }
+
+        Ok(Self {
+            id,
+            members,
+            tilejson,
+            info: TileInfo::new(Format::Mvt, encoding),
+            layers,
+        })
+    }
+
+    /// Convert a member's tile to the encoding of the composite tiles, renaming its layer if needed
+    fn convert_tile(
+        &self,
+        tile: TileData,
+        member: &CompositeMember,
+        src: &dyn Source,
+    ) -> Result<TileData> {
+        if tile.is_empty() {
+            return Ok(tile);
+        }
+        let data = recompress(tile.data, src.get_tile_info(), self.info.encoding);
+        let data = match (data, &member.layer) {
+            (Ok(data), Some(name)) => rename_layers(&data, name).map(Into::into),
+            (data, _) => data,
+        };
+        let data = data.map_err(|e| {
+            CompositeError(self.id.clone(), format!("{e} in tile of {}", member.source))
+        })?;
+        Ok(TileData { data, ..tile })
+    }
+}
+
+#[async_trait]
+impl Source for CompositeSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> TileJSON {
+        self.tilejson.clone()
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Composite
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.info
+    }
+
+    fn clone_source(&self) -> Box<dyn Source> {
+        Box::new(self.clone())
+    }
+
+    fn is_valid_zoom(&self, zoom: u8) -> bool {
+        self.members.iter().any(|(_, s)| s.is_valid_zoom(zoom))
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.members.iter().any(|(_, s)| s.support_url_query())
+    }
+
+    fn get_cache_control(&self) -> Option<String> {
+        most_restrictive_cache_control(self.members.iter().map(|(_, s)| s.get_cache_control()))
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        RateLimit::most_restrictive(self.members.iter().map(|(_, s)| s.get_rate_limit()))
+    }
+
+    fn get_layers(&self) -> Option<&[String]> {
+        self.layers.as_deref()
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, query: &Option<UrlQuery>) -> Result<TileData> {
+        let members: Vec<_> = self
+            .members
+            .iter()
+            .filter(|(_, s)| s.is_valid_zoom(xyz.z))
+            .collect();
+        let tiles = try_join_all(members.iter().map(|(_, s)| s.get_tile(xyz, query))).await?;
+        let tiles = tiles
+            .into_iter()
+            .zip(members)
+            .map(|(tile, (member, src))| self.convert_tile(tile, member, src.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(merge_tiles(&tiles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::parse_cfg;
+    use crate::source::tests::TestSource;
+
+    /// Encode a vector tile with a single layer that only has a name
+    fn layer_tile(name: &str) -> Vec<u8> {
+        let mut tile = vec![0x1A, u8::try_from(name.len() + 2).unwrap(), 0x0A];
+        tile.push(u8::try_from(name.len()).unwrap());
+        tile.extend_from_slice(name.as_bytes());
+        tile
+    }
+
+    fn member(
+        source: &'static str,
+        layer: Option<&str>,
+        format: Format,
+    ) -> (CompositeMember, Box<dyn Source>) {
+        let member = CompositeMember {
+            source: source.to_string(),
+            layer: layer.map(ToString::to_string),
+        };
+        let source = TestSource {
+            layers: Some(vec![source.to_string()]),
+            ..TestSource::new(source, format, &layer_tile(source))
+        };
+        (member, Box::new(source))
+    }
+
+    #[test]
+    fn test_parse_composite_config() {
+        let cfg = parse_cfg(
+            r"
+composites:
+  basemap:
+    - roads
+    - source: other_roads
+      layer: roads2
+",
+        );
+        assert_eq!(
+            cfg.composites.unwrap()["basemap"],
+            vec![
+                CompositeMemberSrc::Id("roads".to_string()),
+                CompositeMemberSrc::Obj(CompositeMember {
+                    source: "other_roads".to_string(),
+                    layer: Some("roads2".to_string()),
+                }),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_composite_source() {
+        let src = CompositeSource::new(
+            "basemap".to_string(),
+            vec![
+                member("roads", None, Format::Mvt),
+                member("water", Some("lakes"), Format::Mvt),
+            ],
+        )
+        .unwrap();
+        assert_eq!(src.get_layers().unwrap(), &["roads", "lakes"]);
+        let tile = src
+            .get_tile(&Xyz { z: 0, x: 0, y: 0 }, &None)
+            .await
+            .unwrap();
+        assert_eq!(
+            tile.data,
+            [layer_tile("roads"), layer_tile("lakes")].concat()
+        );
+
+        let members = vec![
+            member("roads", None, Format::Mvt),
+            member("image", None, Format::Png),
+        ];
+        assert!(CompositeSource::new("basemap".to_string(), members).is_err());
+        assert!(CompositeSource::new("basemap".to_string(), vec![]).is_err());
+    }
+}
@@ -2,28 +2,42 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::future::Future;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 
-use futures::future::try_join_all;
+use futures::future::{try_join, try_join_all};
 use serde::{Deserialize, Serialize};
 use subst::VariableMap;
 
+use crate::composite::{resolve_composites, CompositeConfig};
+use crate::fallback::{resolve_fallbacks, FallbackConfig};
 use crate::file_config::{resolve_files, FileConfigEnum};
+use crate::fonts::{resolve_fonts, FontSources};
 use crate::mbtiles::MbtSource;
-use crate::pg::PgConfig;
+use crate::pg::{PgConfig, PgPool};
 use crate::pmtiles::PmtSource;
 use crate::source::Sources;
 use crate::sprites::{resolve_sprites, SpriteSources};
 use crate::srv::SrvConfig;
+use crate::styles::{resolve_styles, StyleSources};
 use crate::utils::{IdResolver, OneOrMany, Result};
-use crate::Error::{ConfigLoadError, ConfigParseError, NoSources};
+use crate::Error::{
+    ConfigLoadError, ConfigParseError, ConfigSerializeError, ConfigWriteError, NoSources,
+};
 
 pub type UnrecognizedValues = HashMap<String, serde_yaml::Value>;
 
+#[derive(Clone)]
 pub struct AllSources {
     pub sources: Sources,
     pub sprites: SpriteSources,
+    pub fonts: FontSources,
+    pub styles: StyleSources,
+    pub pg_pools: Vec<PgPool>,
+    /// The resolved configuration of these sources, as saved with `--save-config`
+    pub config: Config,
+    /// Keeps the source IDs unique when sources are added while the server is running
+    pub id_resolver: IdResolver,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -43,6 +57,20 @@ pub struct Config {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sprites: Option<FileConfigEnum>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fonts: Option<FileConfigEnum>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub styles: Option<FileConfigEnum>,
+
+    /// Sources that get each tile from the first of several other sources that has it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallbacks: Option<FallbackConfig>,
+
+    /// Vector tile sources that combine the layers of several other sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub composites: Option<CompositeConfig>,
+
     #[serde(flatten)]
     pub unrecognized: UnrecognizedValues,
 }
@@ -83,6 +111,20 @@ impl Config {
             false
         };
 
+        any |= if let Some(cfg) = &mut self.fonts {
+            res.extend(cfg.finalize("fonts.")?);
+            !cfg.is_empty()
+        } else {
+            false
+        };
+
+        any |= if let Some(cfg) = &mut self.styles {
+            res.extend(cfg.finalize("styles.")?);
+            !cfg.is_empty()
+        } else {
+            false
+        };
+
         if any {
             Ok(res)
         } else {
@@ -94,12 +136,14 @@ impl Config {
         let create_pmt_src = &mut PmtSource::new_box;
         let create_mbt_src = &mut MbtSource::new_box;
 
-        let mut sources: Vec<Pin<Box<dyn Future<Output = Result<Sources>>>>> = Vec::new();
+        let mut pg_sources = Vec::new();
         if let Some(v) = self.postgres.as_mut() {
             for s in v.iter_mut() {
-                sources.push(Box::pin(s.resolve(idr.clone())));
+                pg_sources.push(s.resolve(idr.clone()));
             }
         }
+
+        let mut sources: Vec<Pin<Box<dyn Future<Output = Result<Sources>>>>> = Vec::new();
         if self.pmtiles.is_some() {
             let val = resolve_files(&mut self.pmtiles, idr.clone(), "pmtiles", create_pmt_src);
             sources.push(Box::pin(val));
@@ -110,21 +154,57 @@ impl Config {
             sources.push(Box::pin(val));
         }
 
+        let (pg_sources, sources) =
+            try_join(try_join_all(pg_sources), try_join_all(sources)).await?;
+        let (pg_sources, pg_pools): (Vec<_>, Vec<_>) = pg_sources.into_iter().unzip();
+
         // Minor in-efficiency:
         // Sources are added to a BTreeMap, then iterated over into a sort structure and convert back to a BTreeMap.
         // Ideally there should be a vector of values, which is then sorted (in-place?) and converted to a BTreeMap.
-        Ok(AllSources {
-            sources: try_join_all(sources)
-                .await?
+        let mut sources =
+            pg_sources
                 .into_iter()
+                .chain(sources)
                 .fold(Sources::default(), |mut acc, hashmap| {
                     acc.extend(hashmap);
                     acc
-                })
-                .sort(),
-            sprites: resolve_sprites(&mut self.sprites)?,
+                });
+        resolve_fallbacks(self.fallbacks.as_ref(), &mut sources, &idr)?;
+        resolve_composites(self.composites.as_ref(), &mut sources, &idr)?;
+        let sources = sources.sort();
+        let sprites = resolve_sprites(&mut self.sprites)?;
+        let fonts = resolve_fonts(&mut self.fonts)?;
+        let styles = resolve_styles(&mut self.styles)?;
+        Ok(AllSources {
+            sources,
+            sprites,
+            fonts,
+            styles,
+            pg_pools,
+            config: self.clone(),
+            id_resolver: idr,
         })
     }
+
+    /// Save the config as YAML, which can be loaded later with `--config`.
+    /// The config is written to a temporary file first, which then replaces the file,
+    /// so that the file is never left partially written.
+    pub fn save_to_file(&self, file_name: &Path) -> Result<()> {
+        let yaml = serde_yaml::to_string(self).map_err(ConfigSerializeError)?;
+        let mut tmp_name = file_name.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_name = PathBuf::from(tmp_name);
+        let result = File::create(&tmp_name)
+            .and_then(|mut file| {
+                file.write_all(yaml.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| std::fs::rename(&tmp_name, file_name));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_name);
+        }
+        result.map_err(|e| ConfigWriteError(e, file_name.into()))
+    }
 }
 
 pub fn copy_unrecognized_config(
@@ -0,0 +1,372 @@
+use std::collections::{BTreeSet, HashMap};
+
+use async_trait::async_trait;
+use itertools::Itertools as _;
+use log::info;
+use martin_tile_utils::TileInfo;
+use serde::{Deserialize, Serialize};
+use tilejson::{Bounds, TileJSON};
+
+use crate::source::{
+    Source, SourceKind, SourceMember, SourceMembers, Sources, TileData, UrlQuery, Xyz,
+};
+use crate::srv::{merge_tilejson, RateLimit};
+use crate::utils::{is_valid_zoom, most_restrictive_cache_control, IdResolver, Result};
+use crate::Error::FallbackError;
+
+/// Fallback sources by their IDs, each a list of the sources to get the tiles from, in order
+pub type FallbackConfig = HashMap<String, Vec<FallbackMemberSrc>>;
+
+/// A member of a fallback source, either a source ID, or a source ID with the tiles it may serve
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FallbackMemberSrc {
+    Id(String),
+    Obj(FallbackMember),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FallbackMember {
+    /// ID of the source to get the tiles from
+    pub source: String,
+    /// Only get the tiles intersecting these bounds from the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+    /// Only get the tiles at this or higher zoom from the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minzoom: Option<u8>,
+    /// Only get the tiles at this or lower zoom from the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maxzoom: Option<u8>,
+}
+
+impl SourceMember for FallbackMemberSrc {
+    fn source_id(&self) -> &str {
+        match self {
+            Self::Id(source) => source,
+            Self::Obj(member) => &member.source,
+        }
+    }
+}
+
+impl FallbackMemberSrc {
+    #[must_use]
+    pub fn into_member(self) -> FallbackMember {
+        match self {
+            Self::Id(source) => FallbackMember {
+                source,
+                ..Default::default()
+            },
+            Self::Obj(member) => member,
+        }
+    }
+}
+
+impl SourceMember for FallbackMember {
+    fn source_id(&self) -> &str {
+        &self.source
+    }
+}
+
+impl FallbackMember {
+    fn is_valid_zoom(&self, src: &dyn Source, zoom: u8) -> bool {
+        is_valid_zoom(zoom, self.minzoom, self.maxzoom) && src.is_valid_zoom(zoom)
+    }
+
+    /// Check if the tile may be requested from the member's source
+    fn covers(&self, src: &dyn Source, xyz: &Xyz) -> bool {
+        self.is_valid_zoom(src, xyz.z)
+            && self
+                .bounds
+                .map_or(true, |bounds| intersects(&xyz.bounds(), &bounds))
+    }
+}
+
+/// Create the fallback sources from the sources they refer to, and add them to the sources
+pub fn resolve_fallbacks(
+    config: Option<&FallbackConfig>,
+    sources: &mut Sources,
+    idr: &IdResolver,
+) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    for (id, members) in config.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        let members = members
+            .iter()
+            .cloned()
+            .map(FallbackMemberSrc::into_member)
+            .collect();
+        let (id, members) = sources.resolve_members(idr, "fallback", id, members, FallbackError)?;
+        let source = FallbackSource::new(id.clone(), members)?;
+        info!("Configured fallback source {id}");
+        sources.insert(id, Box::new(source));
+    }
+    Ok(())
+}
+
+/// A source that gets each tile from the first of its member sources that has it
+#[derive(Debug, Clone)]
+pub struct FallbackSource {
+    id: String,
+    members: SourceMembers<FallbackMember>,
+    tilejson: TileJSON,
+    info: TileInfo,
+    layers: Option<Vec<String>>,
+}
+
+impl FallbackSource {
+    pub fn new(id: String, members: SourceMembers<FallbackMember>) -> Result<Self> {
+        let Some((_, first)) = members.first() else {
+            return Err(FallbackError(id, "no sources are configured".to_string()));
+        };
+        let info = first.get_tile_info();
+        if let Some((member, src)) = members.iter().find(|(_, s)| s.get_tile_info() != info) {
+            return Err(FallbackError(
+                id,
+                format!(
+                    "all sources must have the same tiles, but {} has {} and {} has {info}",
+                    member.source,
+                    src.get_tile_info(),
+                    members[0].0.source,
+                ),
+            ));
+        }
+
+        let sources = members.iter().map(|(_, s)| s.as_ref()).collect();
+        let mut tilejson = merge_tilejson(sources, String::new());
+        tilejson.tiles = vec![];
+        // Unlike in composite sources, the sources usually have the same layers
+        if let Some(layers) = &mut tilejson.vector_layers {
+            let mut ids = BTreeSet::new();
+            layers.retain(|v| ids.insert(v.id.clone()));
+        }
+        let layers = members
+            .iter()
+            .map(|(_, s)| s.get_layers())
+            .collect::<Option<Vec<_>>>()
+            .map(|v| {
+                v.into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect()
+            });
+        Ok(Self {
+            id,
+            members,
+            tilejson,
+            info,
+            layers,
+        })
+    }
+}
+
+#[async_trait]
+impl Source for FallbackSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> TileJSON {
+        self.tilejson.clone()
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Fallback
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.info
+    }
+
+    fn clone_source(&self) -> Box<dyn Source> {
+        Box::new(self.clone())
+    }
+
+    fn is_valid_zoom(&self, zoom: u8) -> bool {
+        self.members
+            .iter()
+            .any(|(m, s)| m.is_valid_zoom(s.as_ref(), zoom))
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.members.iter().any(|(_, s)| s.support_url_query())
+    }
+
+    fn get_cache_control(&self) -> Option<String> {
+        most_restrictive_cache_control(self.members.iter().map(|(_, s)| s.get_cache_control()))
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        RateLimit::most_restrictive(self.members.iter().map(|(_, s)| s.get_rate_limit()))
+    }
+
+    fn get_layers(&self) -> Option<&[String]> {
+        self.layers.as_deref()
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, query: &Option<UrlQuery>) -> Result<TileData> {
+        for (member, source) in &self.members {
+            if member.covers(source.as_ref(), xyz) {
+                let tile = source.get_tile(xyz, query).await?;
+                if !tile.is_empty() {
+                    return Ok(tile);
+                }
+            }
+        }
+        Ok(TileData::default())
+    }
+}
+
+/// Check if two longitude and latitude bounds overlap, not counting touching edges
+fn intersects(a: &Bounds, b: &Bounds) -> bool {
+    a.left < b.right && b.left < a.right && a.bottom < b.top && b.bottom < a.top
+}
+
+#[cfg(test)]
+mod tests {
+    use martin_tile_utils::Format;
+
+    use super::*;
+    use crate::config::tests::parse_cfg;
+    use crate::source::tests::TestSource;
+
+    fn source(id: &'static str, maxzoom: u8, data: &[u8]) -> Box<dyn Source> {
+        Box::new(TestSource {
+            maxzoom,
+            ..TestSource::new(id, Format::Png, data)
+        })
+    }
+
+    #[test]
+    fn test_parse_fallback_config() {
+        let cfg = parse_cfg(
+            r"
+fallbacks:
+  terrain:
+    - source: regional
+      bounds: [5.9, 45.8, 10.5, 47.8]
+      minzoom: 8
+    - global
+",
+        );
+        let members = &cfg.fallbacks.unwrap()["terrain"];
+        assert_eq!(
+            members,
+            &vec![
+                FallbackMemberSrc::Obj(FallbackMember {
+                    source: "regional".to_string(),
+                    bounds: Some(Bounds::new(5.9, 45.8, 10.5, 47.8)),
+                    minzoom: Some(8),
+                    maxzoom: None,
+                }),
+                FallbackMemberSrc::Id("global".to_string()),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_fallback_source() {
+        let regional = FallbackMember {
+            source: "regional".to_string(),
+            // Switzerland
+            bounds: Some(Bounds::new(5.9, 45.8, 10.5, 47.8)),
+            ..Default::default()
+        };
+        let global = FallbackMember {
+            source: "global".to_string(),
+            ..Default::default()
+        };
+        let src = FallbackSource::new(
+            "fallback".to_string(),
+            vec![
+                (regional.clone(), source("regional", 14, b"regional")),
+                (global.clone(), source("global", 10, b"global")),
+            ],
+        )
+        .unwrap();
+        assert_eq!(src.get_tilejson().maxzoom, Some(14));
+        assert_eq!(src.get_tilejson().name, Some("regional,global".to_string()));
+        assert!(src.is_valid_zoom(12));
+        assert!(!src.is_valid_zoom(15));
+
+        let tile = |z, x, y| {
+            let src = src.clone();
+            async move { src.get_tile(&Xyz { z, x, y }, &None).await.unwrap().data }
+        };
+        // Zurich
+        assert_eq!(tile(8, 134, 89).await, &b"regional"[..]);
+        // Outside of the regional bounds
+        assert_eq!(tile(8, 10, 89).await, &b"global"[..]);
+        assert_eq!(tile(0, 0, 0).await, &b"regional"[..]);
+
+        // The regional source has no tile
+        let src = FallbackSource::new(
+            "fallback".to_string(),
+            vec![
+                (regional.clone(), source("regional", 14, b"")),
+                (global.clone(), source("global", 10, b"global")),
+            ],
+        )
+        .unwrap();
+        let tile = src
+            .get_tile(
+                &Xyz {
+                    z: 8,
+                    x: 134,
+                    y: 89,
+                },
+                &None,
+            )
+            .await;
+        assert_eq!(tile.unwrap().data, &b"global"[..]);
+
+        let jpeg = Box::new(TestSource::new("global", Format::Jpeg, b"global"));
+        assert!(FallbackSource::new(
+            "fallback".to_string(),
+            vec![(regional, source("regional", 14, b"")), (global, jpeg)],
+        )
+        .is_err());
+        assert!(FallbackSource::new("fallback".to_string(), vec![]).is_err());
+    }
+
+    #[test]
+    fn test_fallback_settings() {
+        let member = |source: TestSource| -> (FallbackMember, Box<dyn Source>) {
+            let member = FallbackMember {
+                source: source.id.to_string(),
+                ..Default::default()
+            };
+            (member, Box::new(source))
+        };
+        let regional = TestSource {
+            cache_control: Some("public, max-age=60".to_string()),
+            rate_limit: Some(RateLimit {
+                requests_per_second: 5.0,
+                burst: None,
+            }),
+            ..TestSource::new("regional", Format::Png, b"")
+        };
+        let global = TestSource::new("global", Format::Png, b"");
+        let members = vec![member(regional.clone()), member(global.clone())];
+        let src = FallbackSource::new("fallback".to_string(), members).unwrap();
+        assert_eq!(src.get_cache_control(), regional.cache_control);
+        assert_eq!(src.get_rate_limit(), regional.rate_limit);
+
+        // The most restrictive settings of the sources are used
+        let global = TestSource {
+            cache_control: Some("no-cache".to_string()),
+            rate_limit: Some(RateLimit {
+                requests_per_second: 10.0,
+                burst: Some(1),
+            }),
+            ..global
+        };
+        let members = vec![member(regional.clone()), member(global.clone())];
+        let src = FallbackSource::new("fallback".to_string(), members).unwrap();
+        assert_eq!(src.get_cache_control(), global.cache_control);
+        assert_eq!(src.get_rate_limit(), regional.rate_limit);
+    }
+}
@@ -10,7 +10,9 @@ use serde::{Deserialize, Serialize};
 use crate::config::{copy_unrecognized_config, UnrecognizedValues};
 use crate::file_config::FileError::{InvalidFilePath, InvalidSourceFilePath, IoError};
 use crate::source::{Source, Sources};
-use crate::utils::{sorted_opt_map, Error, IdResolver, OneOrMany};
+use crate::utils::{
+    cache_control_value, deserialize_cache_control, sorted_opt_map, Error, IdResolver, OneOrMany,
+};
 use crate::OneOrMany::{Many, One};
 
 #[derive(thiserror::Error, Debug)]
@@ -115,17 +117,53 @@ pub enum FileConfigSrc {
 
 impl FileConfigSrc {
     pub fn abs_path(&self) -> Result<PathBuf, FileError> {
-        let path = match self {
+        let path = self.get_path();
+        path.canonicalize().map_err(|e| IoError(e, path.clone()))
+    }
+
+    #[must_use]
+    pub fn get_path(&self) -> &PathBuf {
+        match self {
             Self::Path(p) => p,
             Self::Obj(o) => &o.path,
-        };
-        path.canonicalize().map_err(|e| IoError(e, path.clone()))
+        }
+    }
+
+    /// The `Cache-Control` header value configured for this source
+    #[must_use]
+    pub fn get_cache_control(&self) -> Option<String> {
+        match self {
+            Self::Path(_) => None,
+            Self::Obj(o) => cache_control_value(o.cache_control.as_deref(), o.max_age),
+        }
+    }
+
+    /// Whether tiles above the source's maxzoom should be generated from its maxzoom tiles
+    #[must_use]
+    pub fn get_overzoom(&self) -> bool {
+        match self {
+            Self::Path(_) => false,
+            Self::Obj(o) => o.overzoom.unwrap_or_default(),
+        }
     }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct FileConfigSource {
     pub path: PathBuf,
+    /// `Cache-Control` header value of this source responses, overriding the server-wide setting
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_cache_control"
+    )]
+    pub cache_control: Option<String>,
+    /// Number of seconds this source responses may be cached, a shortcut for `cache_control: public, max-age=...`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+    /// Generate the tiles above the source's maxzoom by scaling its tiles at maxzoom
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overzoom: Option<bool>,
 }
 
 impl FileConfigEnum {
@@ -151,7 +189,7 @@ pub async fn resolve_files<Fut>(
     config: &mut Option<FileConfigEnum>,
     idr: IdResolver,
     extension: &str,
-    create_source: &mut impl FnMut(String, PathBuf) -> Fut,
+    create_source: &mut impl FnMut(String, FileConfigSrc) -> Fut,
 ) -> Result<Sources, Error>
 where
     Fut: Future<Output = Result<Box<dyn Source>, FileError>>,
@@ -165,7 +203,7 @@ async fn resolve_int<Fut>(
     config: &mut Option<FileConfigEnum>,
     idr: IdResolver,
     extension: &str,
-    create_source: &mut impl FnMut(String, PathBuf) -> Fut,
+    create_source: &mut impl FnMut(String, FileConfigSrc) -> Fut,
 ) -> Result<Sources, FileError>
 where
     Fut: Future<Output = Result<Box<dyn Source>, FileError>>,
@@ -193,12 +231,7 @@ where
             let id = idr.resolve(&id, can.to_string_lossy().to_string());
             info!("Configured {dup}source {id} from {}", can.display());
             configs.insert(id.clone(), source.clone());
-
-            let path = match source {
-                FileConfigSrc::Obj(pmt) => pmt.path,
-                FileConfigSrc::Path(path) => path,
-            };
-            results.insert(id.clone(), create_source(id, path).await?);
+            results.insert(id.clone(), create_source(id, source).await?);
         }
     }
 
@@ -239,12 +272,7 @@ where
                 info!("Configured source {id} from {}", can.display());
                 files.insert(can);
                 configs.insert(id.clone(), source.clone());
-
-                let path = match source {
-                    FileConfigSrc::Obj(pmt) => pmt.path,
-                    FileConfigSrc::Path(path) => path,
-                };
-                results.insert(id.clone(), create_source(id, path).await?);
+                results.insert(id.clone(), create_source(id, source).await?);
             }
         }
     }
@@ -299,6 +327,7 @@ mod tests {
                     "pm-src2".to_string(),
                     FileConfigSrc::Obj(FileConfigSource {
                         path: PathBuf::from("/tmp/file.ext"),
+                        ..Default::default()
                     })
                 )
             ]))
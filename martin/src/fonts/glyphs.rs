@@ -0,0 +1,266 @@
+// Signed distance field (SDF) glyph rendering, compatible with the glyph PBFs
+// generated by node-fontnik (https://github.com/mapbox/node-fontnik) and used by MapLibre.
+
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+/// Font size in pixels used to render the glyphs
+const FONT_SIZE: f64 = 24.0;
+/// Number of pixels added around each glyph bitmap
+const BUFFER: i32 = 3;
+/// Maximum distance from the glyph outline in pixels encoded in the bitmap
+const RADIUS: f64 = 8.0;
+/// The part of the value range used to encode the distances inside the glyph
+const CUTOFF: f64 = 0.25;
+/// Number of line segments used to approximate each curve of the outline
+const CURVE_STEPS: u16 = 8;
+
+/// A collection of font stacks, each with a range of glyphs.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Glyphs {
+    #[prost(message, repeated, tag = "1")]
+    pub stacks: Vec<Fontstack>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Fontstack {
+    #[prost(string, required, tag = "1")]
+    pub name: String,
+    #[prost(string, required, tag = "2")]
+    pub range: String,
+    #[prost(message, repeated, tag = "3")]
+    pub glyphs: Vec<Glyph>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Glyph {
+    #[prost(uint32, required, tag = "1")]
+    pub id: u32,
+    /// A signed distance field of the glyph with a border of [`BUFFER`] pixels
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub bitmap: Option<Vec<u8>>,
+    #[prost(uint32, required, tag = "3")]
+    pub width: u32,
+    #[prost(uint32, required, tag = "4")]
+    pub height: u32,
+    #[prost(sint32, required, tag = "5")]
+    pub left: i32,
+    #[prost(sint32, required, tag = "6")]
+    pub top: i32,
+    #[prost(uint32, required, tag = "7")]
+    pub advance: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+/// Collects glyph outline as closed rings of points, approximating curves with line segments.
+#[derive(Default)]
+struct RingBuilder {
+    scale: f64,
+    rings: Vec<Vec<Point>>,
+    ring: Vec<Point>,
+}
+
+impl RingBuilder {
+    fn point(&self, x: f32, y: f32) -> Point {
+        Point {
+            x: f64::from(x) * self.scale,
+            y: f64::from(y) * self.scale,
+        }
+    }
+
+    fn last(&self) -> Point {
+        self.ring
+            .last()
+            .copied()
+            .unwrap_or(Point { x: 0.0, y: 0.0 })
+    }
+
+    fn add_curve(&mut self, f: impl Fn(f64) -> Point) {
+        for step in 1..=CURVE_STEPS {
+            self.ring.push(f(f64::from(step) / f64::from(CURVE_STEPS)));
+        }
+    }
+}
+
+impl OutlineBuilder for RingBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.ring.push(self.point(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.ring.push(self.point(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last(), self.point(x1, y1), self.point(x, y));
+        self.add_curve(|t| {
+            let mt = 1.0 - t;
+            Point {
+                x: mt * mt * p0.x + 2.0 * mt * t * p1.x + t * t * p2.x,
+                y: mt * mt * p0.y + 2.0 * mt * t * p1.y + t * t * p2.y,
+            }
+        });
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1) = (self.last(), self.point(x1, y1));
+        let (p2, p3) = (self.point(x2, y2), self.point(x, y));
+        self.add_curve(|t| {
+            let mt = 1.0 - t;
+            let (b0, b1, b2, b3) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+            Point {
+                x: b0 * p0.x + b1 * p1.x + b2 * p2.x + b3 * p3.x,
+                y: b0 * p0.y + b1 * p1.y + b2 * p2.y + b3 * p3.y,
+            }
+        });
+    }
+
+    fn close(&mut self) {
+        if let (Some(first), Some(last)) = (self.ring.first(), self.ring.last()) {
+            if first != last {
+                self.ring.push(*first);
+            }
+            self.rings.push(std::mem::take(&mut self.ring));
+        }
+    }
+}
+
+/// Render a glyph of the given character code as a signed distance field.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+#[must_use]
+pub fn render_sdf_glyph(face: &Face, id: u32, glyph_id: GlyphId) -> Glyph {
+    let scale = FONT_SIZE / f64::from(face.units_per_em());
+    let ascender = (f64::from(face.ascender()) * scale).ceil() as i32;
+    let advance = face
+        .glyph_hor_advance(glyph_id)
+        .map_or(0, |v| (f64::from(v) * scale).round() as u32);
+    let mut glyph = Glyph {
+        id,
+        bitmap: None,
+        width: 0,
+        height: 0,
+        left: 0,
+        top: -ascender,
+        advance,
+    };
+
+    let mut builder = RingBuilder {
+        scale,
+        ..Default::default()
+    };
+    face.outline_glyph(glyph_id, &mut builder);
+    builder.close();
+    let rings = builder.rings;
+
+    let points = rings.iter().flatten();
+    let x_min = points
+        .clone()
+        .map(|p| p.x)
+        .fold(f64::INFINITY, f64::min)
+        .round();
+    let y_min = points
+        .clone()
+        .map(|p| p.y)
+        .fold(f64::INFINITY, f64::min)
+        .round();
+    let x_max = points
+        .clone()
+        .map(|p| p.x)
+        .fold(f64::NEG_INFINITY, f64::max)
+        .round();
+    let y_max = points
+        .map(|p| p.y)
+        .fold(f64::NEG_INFINITY, f64::max)
+        .round();
+    if !(x_max > x_min && y_max > y_min) {
+        // Glyphs without an outline like spaces only have an advance
+        return glyph;
+    }
+
+    glyph.width = (x_max - x_min) as u32;
+    glyph.height = (y_max - y_min) as u32;
+    glyph.left = x_min as i32;
+    glyph.top = y_max as i32 - ascender;
+
+    // Move the outline into the bitmap coordinates, leaving a buffer around it
+    let rings: Vec<Vec<Point>> = rings
+        .into_iter()
+        .map(|ring| {
+            ring.into_iter()
+                .map(|p| Point {
+                    x: p.x - x_min + f64::from(BUFFER),
+                    y: p.y - y_min + f64::from(BUFFER),
+                })
+                .collect()
+        })
+        .collect();
+
+    let width = glyph.width as usize + 2 * BUFFER as usize;
+    let height = glyph.height as usize + 2 * BUFFER as usize;
+    let mut bitmap = vec![0; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let p = Point {
+                x: x as f64 + 0.5,
+                y: y as f64 + 0.5,
+            };
+            let mut dist = min_distance(&rings, p).min(RADIUS) * 256.0 / RADIUS;
+            if is_inside(&rings, p) {
+                dist = -dist;
+            }
+            // Shift the zero so that a few distances inside the glyph fit into the value range
+            let value = (dist + CUTOFF * 256.0).clamp(0.0, 255.0) as u8;
+            // Bitmap rows go from top to bottom, while the font y-axis points up
+            bitmap[(height - y - 1) * width + x] = 255 - value;
+        }
+    }
+    glyph.bitmap = Some(bitmap);
+    glyph
+}
+
+fn segments(rings: &[Vec<Point>]) -> impl Iterator<Item = (Point, Point)> + '_ {
+    rings
+        .iter()
+        .flat_map(|ring| ring.windows(2).map(|w| (w[0], w[1])))
+}
+
+fn min_distance(rings: &[Vec<Point>], p: Point) -> f64 {
+    segments(rings)
+        .map(|(a, b)| {
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let len2 = dx * dx + dy * dy;
+            let t = if len2 > 0.0 {
+                (((p.x - a.x) * dx + (p.y - a.y) * dy) / len2).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            (p.x - a.x - t * dx).hypot(p.y - a.y - t * dy)
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Use the non-zero winding rule, so that the overlapping contours are treated as filled
+fn is_inside(rings: &[Vec<Point>], p: Point) -> bool {
+    let winding: i32 = segments(rings)
+        .map(|(a, b)| {
+            let side = (b.x - a.x) * (p.y - a.y) - (p.x - a.x) * (b.y - a.y);
+            if a.y <= p.y && b.y > p.y && side > 0.0 {
+                1
+            } else if a.y > p.y && b.y <= p.y && side < 0.0 {
+                -1
+            } else {
+                0
+            }
+        })
+        .sum();
+    winding != 0
+}
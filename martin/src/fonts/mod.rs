@@ -0,0 +1,334 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{info, warn};
+use prost::Message;
+use ttf_parser::{fonts_in_collection, name_id, Face, FaceParsingError, Language};
+
+use crate::file_config::{FileConfigEnum, FileError};
+use crate::fonts::glyphs::{render_sdf_glyph, Fontstack, Glyphs};
+
+mod glyphs;
+
+/// Each glyph request must contain exactly this many consecutive characters
+const RANGE_SIZE: u32 = 256;
+/// Glyphs are only served for the Basic Multilingual Plane
+const MAX_CODEPOINT: u32 = 0xFFFF;
+
+const FONT_EXTENSIONS: [&str; 4] = ["ttf", "otf", "ttc", "otc"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum FontError {
+    #[error("Font {0} not found")]
+    FontNotFound(String),
+
+    #[error("Invalid glyph range {0}-{1}: it must start at a multiple of 256 and contain 256 characters, e.g. 0-255")]
+    InvalidFontRange(u32, u32),
+
+    #[error("Unable to parse font {}: {0}", .1.display())]
+    FontParsingError(FaceParsingError, PathBuf),
+}
+
+pub fn resolve_fonts(config: &mut Option<FileConfigEnum>) -> Result<FontSources, FileError> {
+    let Some(cfg) = config else {
+        return Ok(FontSources::default());
+    };
+
+    let cfg = cfg.extract_file_config();
+    let mut results = FontSources::default();
+    let mut paths = Vec::new();
+    let mut configs = HashMap::new();
+
+    if let Some(sources) = cfg.sources {
+        for (id, source) in sources {
+            configs.insert(id.clone(), source.clone());
+            let path = source.abs_path()?;
+            let data = read_font_file(&path)?;
+            if Face::parse(&data, 0).is_err() {
+                return Err(FileError::InvalidSourceFilePath(id, path));
+            }
+            add_font(id, path, Arc::new(data), 0, &mut results);
+        }
+    }
+
+    if let Some(cfg_paths) = cfg.paths {
+        for path in cfg_paths {
+            if path.is_dir() {
+                add_font_dir(&path, &mut results)?;
+            } else if path.is_file() {
+                if !add_font_file(&path, &mut results)? {
+                    return Err(FileError::InvalidFilePath(path));
+                }
+            } else {
+                return Err(FileError::InvalidFilePath(path));
+            }
+            paths.push(path);
+        }
+    }
+
+    *config = FileConfigEnum::new_extended(paths, configs, cfg.unrecognized);
+
+    Ok(results)
+}
+
+fn read_font_file(path: &Path) -> Result<Vec<u8>, FileError> {
+    std::fs::read(path).map_err(|e| FileError::IoError(e, path.to_path_buf()))
+}
+
+/// Recursively add all font files in a directory, ignoring the files that cannot be parsed
+fn add_font_dir(dir: &Path, results: &mut FontSources) -> Result<(), FileError> {
+    let on_err = |e| FileError::IoError(e, dir.to_path_buf());
+    for entry in dir.read_dir().map_err(on_err)? {
+        let path = entry.map_err(on_err)?.path();
+        if path.is_dir() {
+            add_font_dir(&path, results)?;
+        } else if is_font_file(&path) && !add_font_file(&path, results)? {
+            warn!(
+                "Ignoring font file {} that could not be parsed",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn is_font_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            FONT_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+        })
+}
+
+/// Add every font face stored in a file, using the font names as IDs.
+/// Returns false if the file is not a valid font file.
+fn add_font_file(path: &Path, results: &mut FontSources) -> Result<bool, FileError> {
+    let data = Arc::new(read_font_file(path)?);
+    let count = fonts_in_collection(&data).unwrap_or(1);
+    let mut valid = false;
+    for index in 0..count {
+        let Ok(face) = Face::parse(&data, index) else {
+            continue;
+        };
+        valid = true;
+        let Some(id) = get_font_name(&face) else {
+            warn!(
+                "Ignoring font #{index} without a name in {}",
+                path.display()
+            );
+            continue;
+        };
+        if id.contains(',') {
+            warn!(
+                "Ignoring font {id} in {} because its name contains a comma",
+                path.display()
+            );
+            continue;
+        }
+        add_font(id, path.to_path_buf(), data.clone(), index, results);
+    }
+    Ok(valid)
+}
+
+fn add_font(id: String, path: PathBuf, data: Arc<Vec<u8>>, index: u32, results: &mut FontSources) {
+    let disp_path = path.display();
+    match results.0.entry(id) {
+        Entry::Occupied(v) => {
+            warn!("Ignoring duplicate font {} from {disp_path} because it was already configured from {}",
+                v.key(), v.get().path.display());
+        }
+        Entry::Vacant(v) => {
+            info!("Configured font {} from {disp_path}", v.key());
+            v.insert(FontSource { path, data, index });
+        }
+    }
+}
+
+/// Font name in the "{family} {style}" form used by the font stacks, e.g. "Open Sans Bold"
+fn get_font_name(face: &Face) -> Option<String> {
+    let family =
+        get_name(face, name_id::TYPOGRAPHIC_FAMILY).or_else(|| get_name(face, name_id::FAMILY))?;
+    let style = get_name(face, name_id::TYPOGRAPHIC_SUBFAMILY)
+        .or_else(|| get_name(face, name_id::SUBFAMILY));
+    Some(match style {
+        Some(style) => format!("{family} {style}"),
+        None => family,
+    })
+}
+
+/// Get a name from the font's name table, preferring the English one
+fn get_name(face: &Face, id: u16) -> Option<String> {
+    let names: Vec<_> = face
+        .names()
+        .into_iter()
+        .filter(|n| n.name_id == id && n.is_unicode())
+        .collect();
+    names
+        .iter()
+        .find(|n| n.language() == Language::English_UnitedStates)
+        .or_else(|| names.first())
+        .and_then(ttf_parser::name::Name::to_string)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FontSources(HashMap<String, FontSource>);
+
+impl FontSources {
+    pub fn get_font_source(&self, id: &str) -> Result<&FontSource, FontError> {
+        self.0
+            .get(id)
+            .ok_or_else(|| FontError::FontNotFound(id.to_string()))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Given a list of IDs in a format "id1,id2,id3", return a glyph PBF with the characters
+    /// `start..=end`. Each character is rendered with the first font that contains it.
+    /// This function is CPU-intensive, and should not run on the async executor directly.
+    pub fn get_font_range(&self, ids: &str, start: u32, end: u32) -> Result<Vec<u8>, FontError> {
+        if start % RANGE_SIZE != 0
+            || start.checked_add(RANGE_SIZE - 1) != Some(end)
+            || end > MAX_CODEPOINT
+        {
+            return Err(FontError::InvalidFontRange(start, end));
+        }
+
+        let faces = ids
+            .split(',')
+            .map(|id| self.get_font_source(id)?.face())
+            .collect::<Result<Vec<_>, FontError>>()?;
+
+        let glyphs = (start..=end)
+            .filter_map(|code| {
+                let chr = char::from_u32(code)?;
+                faces.iter().find_map(|face| {
+                    let glyph_id = face.glyph_index(chr)?;
+                    Some(render_sdf_glyph(face, code, glyph_id))
+                })
+            })
+            .collect();
+
+        let stack = Fontstack {
+            name: ids.to_string(),
+            range: format!("{start}-{end}"),
+            glyphs,
+        };
+        Ok(Glyphs {
+            stacks: vec![stack],
+        }
+        .encode_to_vec())
+    }
+}
+
+#[derive(Clone)]
+pub struct FontSource {
+    path: PathBuf,
+    data: Arc<Vec<u8>>,
+    /// Index of the font face in a font collection file
+    index: u32,
+}
+
+impl Debug for FontSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FontSource")
+            .field("path", &self.path)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FontSource {
+    fn face(&self) -> Result<Face<'_>, FontError> {
+        Face::parse(&self.data, self.index)
+            .map_err(|e| FontError::FontParsingError(e, self.path.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::file_config::{FileConfig, FileConfigSrc};
+
+    const TUFFY: &str = "Tuffy Regular";
+
+    fn decode(data: &[u8]) -> Fontstack {
+        let mut glyphs = Glyphs::decode(data).unwrap();
+        assert_eq!(glyphs.stacks.len(), 1);
+        glyphs.stacks.remove(0)
+    }
+
+    #[test]
+    fn test_fonts() {
+        let mut cfg = FileConfigEnum::new(vec![PathBuf::from("../tests/fixtures/fonts")]);
+        let fonts = resolve_fonts(&mut cfg).unwrap();
+        assert_eq!(fonts.0.len(), 1);
+
+        let stack = decode(&fonts.get_font_range(TUFFY, 0, 255).unwrap());
+        assert_eq!(stack.name, TUFFY);
+        assert_eq!(stack.range, "0-255");
+
+        let glyph = stack
+            .glyphs
+            .iter()
+            .find(|g| g.id == u32::from('A'))
+            .unwrap();
+        assert!(glyph.width > 0 && glyph.height > 0 && glyph.advance > 0);
+        let bitmap = glyph.bitmap.as_ref().unwrap();
+        assert_eq!(
+            bitmap.len(),
+            (glyph.width as usize + 6) * (glyph.height as usize + 6)
+        );
+        // The corner of the bitmap is outside of the glyph, beyond its edge value of 191
+        assert!(bitmap[0] < 192);
+
+        let space = stack
+            .glyphs
+            .iter()
+            .find(|g| g.id == u32::from(' '))
+            .unwrap();
+        assert!(space.bitmap.is_none());
+        assert!(space.advance > 0);
+
+        // A second range with no characters in the font
+        let stack = decode(&fonts.get_font_range(TUFFY, 0xF000, 0xF0FF).unwrap());
+        assert!(stack.glyphs.is_empty());
+    }
+
+    #[test]
+    fn test_font_stack() {
+        let mut cfg = Some(FileConfigEnum::Config(FileConfig {
+            sources: Some(
+                [(
+                    "tuffy".to_string(),
+                    FileConfigSrc::Path(PathBuf::from("../tests/fixtures/fonts/Tuffy.ttf")),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+            ..Default::default()
+        }));
+        let fonts = resolve_fonts(&mut cfg).unwrap();
+        let stack = decode(&fonts.get_font_range("tuffy,tuffy", 256, 511).unwrap());
+        assert_eq!(stack.name, "tuffy,tuffy");
+        assert_eq!(stack.range, "256-511");
+
+        assert!(matches!(
+            fonts.get_font_range("tuffy,missing", 0, 255),
+            Err(FontError::FontNotFound(id)) if id == "missing"
+        ));
+        for (start, end) in [(0, 256), (1, 256), (256, 255), (65536, 65791)] {
+            assert!(matches!(
+                fonts.get_font_range("tuffy", start, end),
+                Err(FontError::InvalidFontRange(..))
+            ));
+        }
+    }
+}
@@ -8,14 +8,19 @@
 #![allow(clippy::module_name_repetitions)]
 
 pub mod args;
+mod composite;
 mod config;
+mod fallback;
 pub mod file_config;
+pub mod fonts;
 pub mod mbtiles;
+mod overzoom;
 pub mod pg;
 pub mod pmtiles;
 mod source;
 pub mod sprites;
 pub mod srv;
+pub mod styles;
 mod utils;
 
 #[cfg(test)]
@@ -26,10 +31,11 @@ mod test_utils;
 // Must make it accessible as carte::Env from both places when testing.
 #[cfg(test)]
 pub use crate::args::Env;
-pub use crate::config::{read_config, Config};
-pub use crate::source::{Source, Sources, Xyz};
+pub use crate::config::{read_config, AllSources, Config};
+pub use crate::source::{Source, Sources, TileData, Xyz};
 pub use crate::utils::{
-    decode_brotli, decode_gzip, BoolOrObject, Error, IdResolver, OneOrMany, Result,
+    decode_brotli, decode_gzip, decode_zlib, decode_zstd, BoolOrObject, Error, IdResolver,
+    OneOrMany, Result,
 };
 
 // Ensure README.md contains valid code
@@ -4,23 +4,28 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use log::trace;
-use martin_mbtiles::MbtilesPool;
+use log::{trace, warn};
+use martin_mbtiles::{MbtType, MbtilesPool};
 use martin_tile_utils::TileInfo;
 use tilejson::TileJSON;
 
-use crate::file_config::FileError;
 use crate::file_config::FileError::{AquireConnError, InvalidMetadata, IoError};
-use crate::source::{Tile, UrlQuery};
+use crate::file_config::{FileConfigSrc, FileError};
+use crate::overzoom::OverzoomSource;
+use crate::source::{SourceKind, TileData, UrlQuery};
 use crate::utils::is_valid_zoom;
 use crate::{Error, Source, Xyz};
 
 #[derive(Clone)]
 pub struct MbtSource {
     id: String,
+    path: PathBuf,
     mbtiles: Arc<MbtilesPool>,
     tilejson: TileJSON,
     tile_info: TileInfo,
+    /// Known only if the file has a valid schema, otherwise tiles are served without hashes
+    mbt_type: Option<MbtType>,
+    cache_control: Option<String>,
 }
 
 impl Debug for MbtSource {
@@ -35,8 +40,15 @@ impl Debug for MbtSource {
 }
 
 impl MbtSource {
-    pub async fn new_box(id: String, path: PathBuf) -> Result<Box<dyn Source>, FileError> {
-        Ok(Box::new(MbtSource::new(id, path).await?))
+    pub async fn new_box(id: String, source: FileConfigSrc) -> Result<Box<dyn Source>, FileError> {
+        let cache_control = source.get_cache_control();
+        let overzoom = source.get_overzoom();
+        let source = MbtSource::new(id, source.get_path().clone()).await?;
+        let source = Box::new(Self {
+            cache_control,
+            ..source
+        });
+        Ok(OverzoomSource::wrap(source, overzoom))
     }
 
     async fn new(id: String, path: PathBuf) -> Result<Self, FileError> {
@@ -53,23 +65,49 @@ impl MbtSource {
         let meta = mbt
             .get_metadata()
             .await
-            .map_err(|e| InvalidMetadata(e.to_string(), path))?;
+            .map_err(|e| InvalidMetadata(e.to_string(), path.clone()))?;
+
+        let mbt_type = match mbt.detect_type().await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!(
+                    "{e}: tiles in {} will be served without etags",
+                    path.display()
+                );
+                None
+            }
+        };
 
         Ok(Self {
             id,
+            path,
             mbtiles: Arc::new(mbt),
             tilejson: meta.tilejson,
             tile_info: meta.tile_info,
+            mbt_type,
+            cache_control: None,
         })
     }
 }
 
 #[async_trait]
 impl Source for MbtSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
     fn get_tilejson(&self) -> TileJSON {
         self.tilejson.clone()
     }
 
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Mbtiles
+    }
+
+    fn get_cache_control(&self) -> Option<String> {
+        self.cache_control.clone()
+    }
+
     fn get_tile_info(&self) -> TileInfo {
         self.tile_info
     }
@@ -86,13 +124,32 @@ impl Source for MbtSource {
         false
     }
 
-    async fn get_tile(&self, xyz: &Xyz, _url_query: &Option<UrlQuery>) -> Result<Tile, Error> {
-        if let Some(tile) = self
-            .mbtiles
-            .get_tile(xyz.z, xyz.x, xyz.y)
+    async fn check_health(&self) -> Result<(), Error> {
+        // The file may have been deleted while the database connections are still open
+        self.path
+            .metadata()
+            .map_err(|e| IoError(e, self.path.clone()))?;
+        self.mbtiles
+            .get_tile(0, 0, 0)
             .await
-            .map_err(|_| AquireConnError(self.id.clone()))?
-        {
+            .map_err(|_| AquireConnError(self.id.clone()))?;
+        Ok(())
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, _url_query: &Option<UrlQuery>) -> Result<TileData, Error> {
+        let tile = match self.mbt_type {
+            Some(mbt_type @ (MbtType::FlatWithHash | MbtType::Normalized)) => self
+                .mbtiles
+                .get_tile_and_hash(mbt_type, xyz.z, xyz.x, xyz.y)
+                .await
+                .map(|v| v.map(|(tile, hash)| TileData::new(tile).etag(hash))),
+            _ => self
+                .mbtiles
+                .get_tile(xyz.z, xyz.x, xyz.y)
+                .await
+                .map(|v| v.map(TileData::new)),
+        };
+        if let Some(tile) = tile.map_err(|_| AquireConnError(self.id.clone()))? {
             Ok(tile)
         } else {
             trace!(
@@ -102,7 +159,7 @@ impl Source for MbtSource {
                 xyz.y,
                 &self.id
             );
-            Ok(Vec::new())
+            Ok(TileData::default())
         }
     }
 }
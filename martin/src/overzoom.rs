@@ -0,0 +1,245 @@
+use std::io;
+use std::io::Cursor;
+
+use actix_web::web;
+use async_trait::async_trait;
+use image::imageops::FilterType;
+use image::{GenericImageView as _, ImageFormat, ImageOutputFormat};
+use log::warn;
+use martin_tile_utils::{Encoding, Format, TileInfo};
+use tilejson::TileJSON;
+
+use crate::source::{Source, SourceKind, Tile, TileData, UrlQuery, Xyz};
+use crate::srv::RateLimit;
+use crate::utils::{overzoom_vector_tile, recompress, Error, Result};
+
+/// The highest zoom level the tiles can be overzoomed to
+const MAX_ZOOM: u8 = 30;
+
+/// A source serving the tiles above the maxzoom of another source,
+/// created by clipping and scaling up that source's tiles at its maxzoom.
+#[derive(Debug, Clone)]
+pub struct OverzoomSource {
+    source: Box<dyn Source>,
+    maxzoom: u8,
+}
+
+impl OverzoomSource {
+    /// Wrap the source if overzooming is enabled and supported for its tiles
+    #[must_use]
+    pub fn wrap(source: Box<dyn Source>, enabled: bool) -> Box<dyn Source> {
+        if !enabled {
+            return source;
+        }
+        let id = source.get_id();
+        let format = source.get_tile_info().format;
+        if !matches!(format, Format::Mvt | Format::Png | Format::Jpeg) {
+            warn!("Overzooming {format} tiles is not supported, source {id} will be served up to its maxzoom");
+            return source;
+        }
+        let Some(maxzoom) = source.get_tilejson().maxzoom else {
+            warn!("Source {id} has no maxzoom, its tiles will not be overzoomed");
+            return source;
+        };
+        Box::new(Self { source, maxzoom })
+    }
+}
+
+#[async_trait]
+impl Source for OverzoomSource {
+    fn get_id(&self) -> &str {
+        self.source.get_id()
+    }
+
+    fn get_tilejson(&self) -> TileJSON {
+        let mut tilejson = self.source.get_tilejson();
+        tilejson.maxzoom = Some(MAX_ZOOM);
+        tilejson
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        self.source.get_kind()
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.source.get_tile_info()
+    }
+
+    fn clone_source(&self) -> Box<dyn Source> {
+        Box::new(self.clone())
+    }
+
+    fn is_valid_zoom(&self, zoom: u8) -> bool {
+        if zoom > self.maxzoom {
+            zoom <= MAX_ZOOM
+        } else {
+            self.source.is_valid_zoom(zoom)
+        }
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.source.support_url_query()
+    }
+
+    fn get_cache_control(&self) -> Option<String> {
+        self.source.get_cache_control()
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.source.get_rate_limit()
+    }
+
+    fn get_layers(&self) -> Option<&[String]> {
+        self.source.get_layers()
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, query: &Option<UrlQuery>) -> Result<TileData> {
+        if xyz.z <= self.maxzoom {
+            return self.source.get_tile(xyz, query).await;
+        }
+        if xyz.z > MAX_ZOOM {
+            return Ok(TileData::default());
+        }
+        let dz = xyz.z - self.maxzoom;
+        let parent = Xyz {
+            z: self.maxzoom,
+            x: xyz.x >> dz,
+            y: xyz.y >> dz,
+        };
+        let (ox, oy) = (xyz.x - (parent.x << dz), xyz.y - (parent.y << dz));
+
+        let tile = self.source.get_tile(&parent, query).await?;
+        if tile.is_empty() {
+            return Ok(tile);
+        }
+        // Decoding and encoding the tiles is CPU-intensive, so it runs on the blocking thread pool
+        let info = self.get_tile_info();
+        let data = web::block(move || overzoom_tile(tile.data, info, dz, ox, oy))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|v| v.map_err(|e| e.to_string()))
+            .map_err(|e| Error::OverzoomError(self.get_id().to_string(), *xyz, e))?;
+        // All child tiles share the parent's validators, so they must be told apart
+        let etag = tile.etag.map(|v| format!("{v}-{dz}-{ox}-{oy}"));
+        Ok(TileData::new(data)
+            .etag(etag)
+            .last_modified(tile.last_modified))
+    }
+
+    fn get_pool_id(&self) -> Option<&str> {
+        self.source.get_pool_id()
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.source.check_health().await
+    }
+}
+
+/// Create the child tile at the `ox`,`oy` offset `dz` zoom levels below the tile,
+/// keeping the tile's format and encoding
+fn overzoom_tile(tile: Tile, info: TileInfo, dz: u8, ox: u32, oy: u32) -> io::Result<Tile> {
+    if info.format == Format::Mvt {
+        let tile = recompress(tile, info, Encoding::Uncompressed)?;
+        let tile = overzoom_vector_tile(&tile, dz, ox, oy)?;
+        if tile.is_empty() {
+            return Ok(Tile::new());
+        }
+        let uncompressed = info.encoding(Encoding::Uncompressed);
+        recompress(tile.into(), uncompressed, info.encoding)
+    } else {
+        overzoom_raster_tile(&tile, info.format, dz, ox, oy)
+            .map(Tile::from)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Crop the part of the image covered by the child tile, and scale it up to the image size
+fn overzoom_raster_tile(
+    tile: &[u8],
+    format: Format,
+    dz: u8,
+    ox: u32,
+    oy: u32,
+) -> image::ImageResult<Vec<u8>> {
+    let image_format =
+        ImageFormat::from_mime_type(format.content_type()).unwrap_or(ImageFormat::Png);
+    let image = image::load_from_memory_with_format(tile, image_format)?;
+    let (width, height) = image.dimensions();
+    let (x, crop_width) = crop_range(width, dz, ox);
+    let (y, crop_height) = crop_range(height, dz, oy);
+    let image = image.crop_imm(x, y, crop_width, crop_height).resize_exact(
+        width,
+        height,
+        FilterType::Triangle,
+    );
+
+    let mut data = Cursor::new(Vec::new());
+    let output = if format == Format::Jpeg {
+        ImageOutputFormat::Jpeg(90)
+    } else {
+        ImageOutputFormat::Png
+    };
+    image.write_to(&mut data, output)?;
+    Ok(data.into_inner())
+}
+
+/// The first pixel and the number of pixels of the `offset` child tile along one axis,
+/// rounded outwards to whole pixels, but always at least one pixel
+#[allow(clippy::cast_possible_truncation)]
+fn crop_range(size: u32, dz: u8, offset: u32) -> (u32, u32) {
+    let (size, offset) = (u64::from(size), u64::from(offset));
+    let start = (offset * size) >> dz;
+    let end = ((offset + 1) * size + (1 << dz) - 1) >> dz;
+    (start as u32, (end - start).max(1) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::source::tests::TestSource;
+    use crate::source::Sources;
+
+    #[test]
+    fn test_overzoom_tilejson() {
+        let mut source = TestSource::new("test", Format::Png, b"");
+        source.maxzoom = 5;
+        let source = OverzoomSource::wrap(Box::new(source), true);
+        assert_eq!(source.get_tilejson().maxzoom, Some(MAX_ZOOM));
+        assert!(source.is_valid_zoom(MAX_ZOOM));
+        assert!(!source.is_valid_zoom(MAX_ZOOM + 1));
+
+        let mut sources = Sources::default();
+        sources.insert("test".to_string(), source);
+        let catalog = serde_json::to_value(sources.get_catalog()).unwrap();
+        assert_eq!(catalog["tiles"]["test"]["maxzoom"], MAX_ZOOM);
+    }
+
+    #[test]
+    fn test_crop_range() {
+        assert_eq!(crop_range(256, 1, 0), (0, 128));
+        assert_eq!(crop_range(256, 1, 1), (128, 128));
+        assert_eq!(crop_range(256, 3, 5), (160, 32));
+        assert_eq!(crop_range(256, 10, 1023), (255, 1));
+        assert_eq!(crop_range(256, 10, 1), (0, 1));
+    }
+
+    #[test]
+    fn test_overzoom_raster_tile() {
+        // Each quadrant of the image has its own color
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+        let image = RgbImage::from_fn(8, 8, |x, y| Rgb(colors[(x / 4 + y / 4 * 2) as usize]));
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        let png = png.into_inner();
+
+        for (i, color) in (0_u32..).zip(colors.iter()) {
+            let (ox, oy) = (i % 2, i / 2);
+            let tile = overzoom_raster_tile(&png, Format::Png, 1, ox, oy).unwrap();
+            let tile = image::load_from_memory(&tile).unwrap().into_rgb8();
+            assert_eq!(tile.dimensions(), (8, 8));
+            assert!(tile.pixels().all(|p| p.0 == *color));
+        }
+    }
+}
@@ -6,13 +6,20 @@ use crate::config::{copy_unrecognized_config, UnrecognizedValues};
 use crate::pg::config_function::FuncInfoSources;
 use crate::pg::config_table::TableInfoSources;
 use crate::pg::configurator::PgBuilder;
+use crate::pg::pool::PgPool;
 use crate::pg::Result;
-use crate::source::Sources;
+use crate::source::{SourceKind, Sources};
+use crate::srv::RateLimit;
 use crate::utils::{sorted_opt_map, BoolOrObject, IdResolver, OneOrMany};
 
 pub trait PgInfo {
     fn format_id(&self) -> String;
     fn to_tilejson(&self, source_id: String) -> TileJSON;
+    fn get_cache_control(&self) -> Option<String>;
+    fn get_rate_limit(&self) -> Option<RateLimit>;
+    /// Names of the MVT layers in the tiles of this source, if known in advance
+    fn get_layers(&self, source_id: &str) -> Option<Vec<String>>;
+    fn get_kind(&self) -> SourceKind;
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -111,8 +118,21 @@ impl PgConfig {
         Ok(res)
     }
 
-    pub async fn resolve(&mut self, id_resolver: IdResolver) -> crate::Result<Sources> {
-        let pg = PgBuilder::new(self, id_resolver).await?;
+    /// Discover and create all sources, returning them together with the connection pool they use
+    pub async fn resolve(&mut self, id_resolver: IdResolver) -> crate::Result<(Sources, PgPool)> {
+        let pool = PgPool::new(self).await?;
+        let sources = self.resolve_with_pool(id_resolver, pool.clone()).await?;
+        Ok((sources, pool))
+    }
+
+    /// Create the sources of this configuration using an existing connection pool,
+    /// replacing the configured tables and functions with the resolved ones.
+    pub async fn resolve_with_pool(
+        &mut self,
+        id_resolver: IdResolver,
+        pool: PgPool,
+    ) -> crate::Result<Sources> {
+        let pg = PgBuilder::new(self, id_resolver, pool);
         let ((mut tables, tbl_info), (funcs, func_info)) =
             try_join(pg.instantiate_tables(), pg.instantiate_functions()).await?;
 
@@ -4,6 +4,9 @@ use tilejson::{Bounds, TileJSON};
 use crate::config::UnrecognizedValues;
 use crate::pg::config::PgInfo;
 use crate::pg::utils::{patch_json, InfoMap};
+use crate::source::SourceKind;
+use crate::srv::RateLimit;
+use crate::utils::{cache_control_value, deserialize_cache_control};
 
 pub type FuncInfoSources = InfoMap<FunctionInfo>;
 
@@ -30,6 +33,22 @@ pub struct FunctionInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bounds: Option<Bounds>,
 
+    /// `Cache-Control` header value of this source responses, overriding the server-wide setting
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_cache_control"
+    )]
+    pub cache_control: Option<String>,
+
+    /// Number of seconds this source responses may be cached, a shortcut for `cache_control: public, max-age=...`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+
+    /// Tile request rate limit of this source, overriding the server-wide `rate_limit`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+
     /// TileJSON provided by the SQL function comment. Not serialized.
     #[serde(skip)]
     pub tilejson: Option<serde_json::Value>,
@@ -84,4 +103,21 @@ impl PgInfo for FunctionInfo {
         tilejson.bounds = self.bounds;
         patch_json(tilejson, &self.tilejson)
     }
+
+    fn get_cache_control(&self) -> Option<String> {
+        cache_control_value(self.cache_control.as_deref(), self.max_age)
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    fn get_layers(&self, _source_id: &str) -> Option<Vec<String>> {
+        // Functions may return any layers
+        None
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::PgFunction
+    }
 }
@@ -6,7 +6,9 @@ use tilejson::{Bounds, TileJSON, VectorLayer};
 use crate::config::UnrecognizedValues;
 use crate::pg::config::PgInfo;
 use crate::pg::utils::{patch_json, InfoMap};
-use crate::utils::sorted_opt_map;
+use crate::source::SourceKind;
+use crate::srv::RateLimit;
+use crate::utils::{cache_control_value, deserialize_cache_control, sorted_opt_map};
 
 pub type TableInfoSources = InfoMap<TableInfo>;
 
@@ -55,6 +57,22 @@ pub struct TableInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bounds: Option<Bounds>,
 
+    /// `Cache-Control` header value of this source responses, overriding the server-wide setting
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_cache_control"
+    )]
+    pub cache_control: Option<String>,
+
+    /// Number of seconds this source responses may be cached, a shortcut for `cache_control: public, max-age=...`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+
+    /// Tile request rate limit of this source, overriding the server-wide `rate_limit`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+
     /// Tile extent in tile coordinate space
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extent: Option<u32>,
@@ -112,4 +130,23 @@ impl PgInfo for TableInfo {
         tilejson.vector_layers = Some(vec![layer]);
         patch_json(tilejson, &self.tilejson)
     }
+
+    fn get_cache_control(&self) -> Option<String> {
+        cache_control_value(self.cache_control.as_deref(), self.max_age)
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    fn get_layers(&self, source_id: &str) -> Option<Vec<String>> {
+        Some(vec![self
+            .layer_id
+            .clone()
+            .unwrap_or_else(|| source_id.to_string())])
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::PgTable
+    }
 }
@@ -47,10 +47,9 @@ pub struct PgBuilder {
 }
 
 impl PgBuilder {
-    pub async fn new(config: &PgConfig, id_resolver: IdResolver) -> Result<Self> {
-        let pool = PgPool::new(config).await?;
-
-        Ok(Self {
+    #[must_use]
+    pub fn new(config: &PgConfig, id_resolver: IdResolver, pool: PgPool) -> Self {
+        Self {
             pool,
             default_srid: config.default_srid,
             disable_bounds: config.disable_bounds.unwrap_or_default(),
@@ -60,7 +59,7 @@ impl PgBuilder {
             functions: config.functions.clone().unwrap_or_default(),
             auto_functions: new_auto_publish(config, true),
             auto_tables: new_auto_publish(config, false),
-        })
+        }
     }
 
     // FIXME: this function has gotten too long due to the new formatting rules, need to be refactored
@@ -255,12 +254,7 @@ impl PgBuilder {
     }
 
     fn add_func_src(&self, sources: &mut Sources, id: String, info: &impl PgInfo, sql: PgSqlInfo) {
-        let source = PgSource::new(
-            id.clone(),
-            sql,
-            info.to_tilejson(id.clone()),
-            self.pool.clone(),
-        );
+        let source = PgSource::new(id.clone(), sql, info, self.pool.clone());
         sources.insert(id, Box::new(source));
     }
 }
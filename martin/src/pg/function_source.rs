@@ -1,6 +1,7 @@
 use std::fmt::Write;
 use std::iter::zip;
 
+use itertools::Itertools;
 use log::{debug, warn};
 use postgres_protocol::escape::escape_identifier;
 use serde_json::Value;
@@ -80,11 +81,12 @@ pub async fn query_available_function(pool: &PgPool) -> Result<SqlFuncInfoMapMap
 
             // TODO: Rewrite as a if-let chain:  if Some(names) = output_record_names && output_type == "record" { ... }
             let ret_inf = if let (Some(names), "record") = (output_record_names, output_type.as_str()) {
-                 // SELECT mvt FROM "public"."function_zxy_row2"(
+                 // SELECT mvt, key FROM "public"."function_zxy_row2"(
                  //    "z" => $1::integer, "x" => $2::integer, "y" => $3::integer
                  // );
+                 // The optional second column is the tile etag.
                  query.insert_str(0, " FROM ");
-                 query.insert_str(0, &escape_identifier(names[0].as_str()));
+                 query.insert_str(0, &names.iter().map(|v| escape_identifier(v)).join(", "));
                  query.insert_str(0, "SELECT ");
                  format!("[{}]", names.join(", "))
              } else {
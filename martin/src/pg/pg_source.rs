@@ -8,10 +8,12 @@ use martin_tile_utils::Format::Mvt;
 use martin_tile_utils::TileInfo;
 use tilejson::TileJSON;
 
+use crate::pg::config::PgInfo;
 use crate::pg::pool::PgPool;
 use crate::pg::utils::query_to_json;
 use crate::pg::PgError::{GetTileError, GetTileWithQueryError, PrepareQueryError};
-use crate::source::{Source, Tile, UrlQuery, Xyz};
+use crate::source::{Source, SourceKind, TileData, UrlQuery, Xyz};
+use crate::srv::RateLimit;
 use crate::utils::{is_valid_zoom, Result};
 
 #[derive(Clone, Debug)]
@@ -20,26 +22,54 @@ pub struct PgSource {
     info: PgSqlInfo,
     pool: PgPool,
     tilejson: TileJSON,
+    cache_control: Option<String>,
+    rate_limit: Option<RateLimit>,
+    layers: Option<Vec<String>>,
+    kind: SourceKind,
 }
 
 impl PgSource {
     #[must_use]
-    pub fn new(id: String, info: PgSqlInfo, tilejson: TileJSON, pool: PgPool) -> Self {
+    pub fn new(id: String, sql: PgSqlInfo, info: &impl PgInfo, pool: PgPool) -> Self {
         Self {
+            tilejson: info.to_tilejson(id.clone()),
+            cache_control: info.get_cache_control(),
+            rate_limit: info.get_rate_limit(),
+            layers: info.get_layers(&id),
+            kind: info.get_kind(),
             id,
-            info,
+            info: sql,
             pool,
-            tilejson,
         }
     }
 }
 
 #[async_trait]
 impl Source for PgSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
     fn get_tilejson(&self) -> TileJSON {
         self.tilejson.clone()
     }
 
+    fn get_kind(&self) -> SourceKind {
+        self.kind
+    }
+
+    fn get_cache_control(&self) -> Option<String> {
+        self.cache_control.clone()
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    fn get_layers(&self) -> Option<&[String]> {
+        self.layers.as_deref()
+    }
+
     fn get_tile_info(&self) -> TileInfo {
         TileInfo::new(Mvt, Uncompressed)
     }
@@ -56,7 +86,15 @@ impl Source for PgSource {
         self.info.use_url_query
     }
 
-    async fn get_tile(&self, xyz: &Xyz, url_query: &Option<UrlQuery>) -> Result<Tile> {
+    fn get_pool_id(&self) -> Option<&str> {
+        Some(self.pool.get_id())
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        Ok(self.pool.check().await?)
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, url_query: &Option<UrlQuery>) -> Result<TileData> {
         let empty_query = HashMap::new();
         let url_query = url_query.as_ref().unwrap_or(&empty_query);
         let conn = self.pool.get().await?;
@@ -99,8 +137,15 @@ impl Source for PgSource {
             .await
         };
 
+        // Functions returning a [bytea, text] row provide the tile etag as the second column
         let tile = tile
-            .map(|row| row.and_then(|r| r.get::<_, Option<Tile>>(0)))
+            .map(|row| {
+                row.and_then(|r| {
+                    let etag = if r.len() > 1 { r.get(1) } else { None };
+                    r.get::<_, Option<Vec<u8>>>(0)
+                        .map(|data| TileData::new(data).etag(etag))
+                })
+            })
             .map_err(|e| {
                 if self.support_url_query() {
                     GetTileWithQueryError(e, self.id.to_string(), *xyz, url_query.clone())
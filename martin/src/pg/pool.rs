@@ -1,4 +1,4 @@
-use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod, Status};
 use log::{info, warn};
 use semver::Version;
 
@@ -23,6 +23,7 @@ pub struct PgPool {
     pool: Pool,
     // When true, we can use margin parameter in ST_TileEnvelope
     margin: bool,
+    postgis_version: Version,
 }
 
 impl PgPool {
@@ -73,7 +74,12 @@ SELECT
         }
 
         let margin = version >= RECOMMENDED_POSTGIS_VER;
-        Ok(Self { id, pool, margin })
+        Ok(Self {
+            id,
+            pool,
+            margin,
+            postgis_version: version,
+        })
     }
 
     pub async fn get(&self) -> Result<Object> {
@@ -85,10 +91,31 @@ SELECT
         self.id.as_str()
     }
 
+    /// Current number of connections in the pool, idle connections, and requests waiting for one
+    #[must_use]
+    pub fn status(&self) -> Status {
+        self.pool.status()
+    }
+
     #[must_use]
     pub fn supports_tile_margin(&self) -> bool {
         self.margin
     }
+
+    #[must_use]
+    pub fn postgis_version(&self) -> &Version {
+        &self.postgis_version
+    }
+
+    /// Make sure the database is reachable by running a trivial query
+    pub async fn check(&self) -> Result<()> {
+        self.get()
+            .await?
+            .simple_query("SELECT 1")
+            .await
+            .map_err(|e| PostgresError(e, "checking database connection"))?;
+        Ok(())
+    }
 }
 
 async fn get_conn(pool: &Pool, id: &str) -> Result<Object> {
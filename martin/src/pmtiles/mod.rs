@@ -2,6 +2,7 @@ use std::fmt::{Debug, Formatter};
 use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use log::{trace, warn};
@@ -11,9 +12,10 @@ use pmtiles::mmap::MmapBackend;
 use pmtiles::{Compression, TileType};
 use tilejson::TileJSON;
 
-use crate::file_config::FileError;
 use crate::file_config::FileError::{InvalidMetadata, IoError};
-use crate::source::{Source, Tile, UrlQuery, Xyz};
+use crate::file_config::{FileConfigSrc, FileError};
+use crate::overzoom::OverzoomSource;
+use crate::source::{Source, SourceKind, TileData, UrlQuery, Xyz};
 use crate::utils::is_valid_zoom;
 use crate::Error;
 
@@ -24,6 +26,9 @@ pub struct PmtSource {
     pmtiles: Arc<AsyncPmTilesReader<MmapBackend>>,
     tilejson: TileJSON,
     tile_info: TileInfo,
+    /// The file is memory-mapped, so its modification time when opened identifies the served data
+    modified: Option<SystemTime>,
+    cache_control: Option<String>,
 }
 
 impl Debug for PmtSource {
@@ -33,11 +38,19 @@ impl Debug for PmtSource {
 }
 
 impl PmtSource {
-    pub async fn new_box(id: String, path: PathBuf) -> Result<Box<dyn Source>, FileError> {
-        Ok(Box::new(PmtSource::new(id, path).await?))
+    pub async fn new_box(id: String, source: FileConfigSrc) -> Result<Box<dyn Source>, FileError> {
+        let cache_control = source.get_cache_control();
+        let overzoom = source.get_overzoom();
+        let source = PmtSource::new(id, source.get_path().clone()).await?;
+        let source = Box::new(Self {
+            cache_control,
+            ..source
+        });
+        Ok(OverzoomSource::wrap(source, overzoom))
     }
 
     async fn new(id: String, path: PathBuf) -> Result<Self, FileError> {
+        let modified = path.metadata().and_then(|m| m.modified()).ok();
         let backend = MmapBackend::try_from(path.as_path())
             .await
             .map_err(|e| {
@@ -108,16 +121,30 @@ impl PmtSource {
             pmtiles: Arc::new(reader),
             tilejson,
             tile_info: format,
+            modified,
+            cache_control: None,
         })
     }
 }
 
 #[async_trait]
 impl Source for PmtSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
     fn get_tilejson(&self) -> TileJSON {
         self.tilejson.clone()
     }
 
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Pmtiles
+    }
+
+    fn get_cache_control(&self) -> Option<String> {
+        self.cache_control.clone()
+    }
+
     fn get_tile_info(&self) -> TileInfo {
         self.tile_info
     }
@@ -134,14 +161,27 @@ impl Source for PmtSource {
         false
     }
 
-    async fn get_tile(&self, xyz: &Xyz, _url_query: &Option<UrlQuery>) -> Result<Tile, Error> {
-        // TODO: optimize to return Bytes
+    async fn check_health(&self) -> Result<(), Error> {
+        // The file is memory-mapped, so make sure it was not removed or replaced by a directory
+        let meta = self
+            .path
+            .metadata()
+            .map_err(|e| IoError(e, self.path.clone()))?;
+        if meta.is_file() {
+            Ok(())
+        } else {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, "not a regular file");
+            Err(IoError(e, self.path.clone()))?
+        }
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, _url_query: &Option<UrlQuery>) -> Result<TileData, Error> {
         if let Some(t) = self
             .pmtiles
             .get_tile(xyz.z, u64::from(xyz.x), u64::from(xyz.y))
             .await
         {
-            Ok(t.data.to_vec())
+            Ok(TileData::new(t.data).last_modified(self.modified))
         } else {
             trace!(
                 "Couldn't find tile data in {}/{}/{} of {}",
@@ -150,7 +190,7 @@ impl Source for PmtSource {
                 xyz.y,
                 &self.id
             );
-            Ok(Vec::new())
+            Ok(TileData::default())
         }
     }
 }
@@ -1,17 +1,21 @@
 use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
 use std::fmt::{Debug, Display, Formatter};
+use std::time::SystemTime;
 
 use actix_web::error::ErrorNotFound;
 use async_trait::async_trait;
+use bytes::Bytes;
 use itertools::Itertools;
 use log::debug;
-use martin_tile_utils::TileInfo;
+use martin_tile_utils::{Encoding, Format, TileInfo};
 use serde::{Deserialize, Serialize};
-use tilejson::TileJSON;
+use tilejson::{Bounds, Center, TileJSON, VectorLayer};
 
-use crate::utils::Result;
+use crate::srv::RateLimit;
+use crate::utils::{Error, IdResolver, LayerFilter, Result};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Xyz {
     pub z: u8,
     pub x: u32,
@@ -28,9 +32,97 @@ impl Display for Xyz {
     }
 }
 
-pub type Tile = Vec<u8>;
+impl Xyz {
+    /// The highest zoom level whose tile coordinates fit in `u32`
+    pub const MAX_ZOOM: u8 = 31;
+
+    /// Check that the zoom level is supported, and that the tile exists at that zoom level
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.z <= Self::MAX_ZOOM && self.x >> self.z == 0 && self.y >> self.z == 0
+    }
+
+    /// The Web Mercator tile at the zoom level containing the point,
+    /// or the nearest tile if the point is outside of the tile grid
+    #[must_use]
+    pub fn containing(z: u8, lon: f64, lat: f64) -> Self {
+        let z = z.min(Self::MAX_ZOOM);
+        let size = 2_f64.powi(i32::from(z));
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let clamp = |v: f64| v.floor().clamp(0.0, size - 1.0) as u32;
+        let lat = lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+        Self {
+            z,
+            x: clamp((lon + 180.0) / 360.0 * size),
+            y: clamp((1.0 - lat.tan().asinh() / PI) / 2.0 * size),
+        }
+    }
+
+    /// Bounds of the Web Mercator tile in degrees
+    #[must_use]
+    pub fn bounds(&self) -> Bounds {
+        let size = 2_f64.powi(i32::from(self.z));
+        let lon = |x: f64| x / size * 360.0 - 180.0;
+        let lat = |y: f64| (PI * (1.0 - 2.0 * y / size)).sinh().atan().to_degrees();
+        let (x, y) = (f64::from(self.x), f64::from(self.y));
+        Bounds::new(lon(x), lat(y + 1.0), lon(x + 1.0), lat(y))
+    }
+}
+
+/// Tile content, cheap to clone and to pass to the HTTP response without copying
+pub type Tile = Bytes;
 pub type UrlQuery = HashMap<String, String>;
 
+/// Tile data together with the optional validators that allow clients to cache it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TileData {
+    pub data: Tile,
+    /// Opaque tile version identifier, e.g. a hash of the tile content
+    pub etag: Option<String>,
+    /// The last time the tile data was modified
+    pub last_modified: Option<SystemTime>,
+}
+
+impl TileData {
+    #[must_use]
+    pub fn new(data: impl Into<Tile>) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    #[must_use]
+    pub fn etag(self, etag: Option<String>) -> Self {
+        Self { etag, ..self }
+    }
+
+    #[must_use]
+    pub fn last_modified(self, last_modified: Option<SystemTime>) -> Self {
+        Self {
+            last_modified,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for TileData {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(data)
+    }
+}
+
+impl From<Tile> for TileData {
+    fn from(data: Tile) -> Self {
+        Self::new(data)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct Sources {
     tiles: HashMap<String, Box<dyn Source>>,
@@ -49,6 +141,7 @@ impl Sources {
                     .into_iter()
                     .sorted_by(|a, b| a.0.cmp(&b.0))
                     .collect(),
+                sprites: self.catalog.sprites,
             },
         }
     }
@@ -61,16 +154,28 @@ impl Sources {
         self.catalog.tiles.insert(
             id.clone(),
             SourceEntry {
+                kind: source.get_kind(),
                 content_type: info.format.content_type().to_string(),
                 content_encoding: info.encoding.content_encoding().map(ToString::to_string),
                 name: tilejson.name.filter(|v| v != &id),
                 description: tilejson.description,
                 attribution: tilejson.attribution,
+                minzoom: tilejson.minzoom,
+                maxzoom: tilejson.maxzoom,
+                bounds: tilejson.bounds,
+                center: tilejson.center,
+                vector_layers: tilejson.vector_layers.filter(|v| !v.is_empty()),
+                url_query: source.support_url_query(),
             },
         );
         self.tiles.insert(id, source);
     }
 
+    pub fn remove(&mut self, id: &str) -> Option<Box<dyn Source>> {
+        self.catalog.tiles.remove(id);
+        self.tiles.remove(id)
+    }
+
     pub fn extend(&mut self, other: Sources) {
         for (k, v) in other.catalog.tiles {
             self.catalog.tiles.insert(k, v);
@@ -78,6 +183,13 @@ impl Sources {
         self.tiles.extend(other.tiles);
     }
 
+    /// All sources with their IDs, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &dyn Source)> {
+        self.tiles
+            .iter()
+            .map(|(id, src)| (id.as_str(), src.as_ref()))
+    }
+
     #[must_use]
     pub fn get_catalog(&self) -> &SourceCatalog {
         &self.catalog
@@ -91,6 +203,36 @@ impl Sources {
             .as_ref())
     }
 
+    /// Get the member sources of a source built from other sources, e.g. a composite source,
+    /// and resolve its ID to a unique one named after its `kind` and its members' sources
+    pub fn resolve_members<M: SourceMember>(
+        &self,
+        idr: &IdResolver,
+        kind: &str,
+        id: &str,
+        members: Vec<M>,
+        error: fn(String, String) -> Error,
+    ) -> Result<(String, SourceMembers<M>)> {
+        let unique_name = format!(
+            "{kind}:{}",
+            members.iter().map(SourceMember::source_id).join(",")
+        );
+        let members = members
+            .into_iter()
+            .map(|member| {
+                let source = self
+                    .get_source(member.source_id())
+                    .map_err(|_| {
+                        let msg = format!("unknown source {}", member.source_id());
+                        error(id.to_string(), msg)
+                    })?
+                    .clone_source();
+                Ok((member, source))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((idr.resolve(id, unique_name), members))
+    }
+
     pub fn get_sources(
         &self,
         source_ids: &str,
@@ -104,9 +246,15 @@ impl Sources {
             let src_inf = src.get_tile_info();
             use_url_query |= src.support_url_query();
 
-            // make sure all sources have the same format
+            // make sure all sources have the same format. Vector tiles with different
+            // encodings can be merged after decoding them, so the result is uncompressed.
+            // Raster images of different formats are blended into the first source's format.
             match info {
                 Some(inf) if inf == src_inf => {}
+                Some(inf) if inf.format == Format::Mvt && src_inf.format == Format::Mvt => {
+                    info = Some(inf.encoding(Encoding::Uncompressed));
+                }
+                Some(inf) if is_blendable(inf.format) && is_blendable(src_inf.format) => {}
                 Some(inf) => Err(ErrorNotFound(format!(
                     "Cannot merge sources with {inf} with {src_inf}"
                 )))?,
@@ -134,12 +282,61 @@ impl Sources {
         }
         is_valid
     }
+
+    /// Check if the source may have any of the requested layers
+    pub fn check_layers(src: &dyn Source, id: &str, layers: Option<&LayerFilter>) -> bool {
+        let is_requested = match (layers, src.get_layers()) {
+            (Some(filter), Some(src_layers)) => src_layers.iter().any(|v| filter.contains(v)),
+            _ => true,
+        };
+        if !is_requested {
+            debug!("Source {id} has none of the requested layers");
+        }
+        is_requested
+    }
+}
+
+/// Raster image formats that can be decoded and blended together in a composite source
+#[must_use]
+pub fn is_blendable(format: Format) -> bool {
+    matches!(format, Format::Png | Format::Jpeg | Format::Webp)
+}
+
+/// The type of a tile source, as listed in the catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    PgTable,
+    PgFunction,
+    Mbtiles,
+    Pmtiles,
+    Fallback,
+    Composite,
+}
+
+impl SourceKind {
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "pg_table" => Self::PgTable,
+            "pg_function" => Self::PgFunction,
+            "mbtiles" => Self::Mbtiles,
+            "pmtiles" => Self::Pmtiles,
+            "fallback" => Self::Fallback,
+            "composite" => Self::Composite,
+            _ => None?,
+        })
+    }
 }
 
 #[async_trait]
-pub trait Source: Send + Debug {
+pub trait Source: Send + Sync + Debug {
+    fn get_id(&self) -> &str;
+
     fn get_tilejson(&self) -> TileJSON;
 
+    fn get_kind(&self) -> SourceKind;
+
     fn get_tile_info(&self) -> TileInfo;
 
     fn clone_source(&self) -> Box<dyn Source>;
@@ -148,7 +345,42 @@ pub trait Source: Send + Debug {
 
     fn support_url_query(&self) -> bool;
 
-    async fn get_tile(&self, xyz: &Xyz, query: &Option<UrlQuery>) -> Result<Tile>;
+    /// The `Cache-Control` header value of this source's tile and `TileJSON` responses, if configured
+    fn get_cache_control(&self) -> Option<String> {
+        None
+    }
+
+    /// The tile request rate limit of this source, overriding the server-wide limit
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        None
+    }
+
+    /// Names of the MVT layers in this source's tiles, if known in advance
+    fn get_layers(&self) -> Option<&[String]> {
+        None
+    }
+
+    async fn get_tile(&self, xyz: &Xyz, query: &Option<UrlQuery>) -> Result<TileData>;
+
+    /// The ID of the database connection pool of this source. The pool is checked once
+    /// for all of its sources, instead of calling [`Source::check_health`] for each of them.
+    fn get_pool_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Make sure the source can still serve tiles, e.g. that its database is reachable
+    async fn check_health(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The members of a source built from other sources, each with the source it gets its tiles from
+pub type SourceMembers<M> = Vec<(M, Box<dyn Source>)>;
+
+/// A member of a source built from other sources, e.g. a composite or a fallback source
+pub trait SourceMember {
+    /// ID of the source the member gets its tiles from
+    fn source_id(&self) -> &str;
 }
 
 impl Clone for Box<dyn Source> {
@@ -157,13 +389,49 @@ impl Clone for Box<dyn Source> {
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct SourceCatalog {
     tiles: BTreeMap<String, SourceEntry>,
+    /// IDs of the sprite sources
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sprites: Vec<String>,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+impl SourceCatalog {
+    /// Create a copy of the catalog with only the tile sources matching the predicate
+    #[must_use]
+    pub fn filter(&self, predicate: impl Fn(&str, &SourceEntry) -> bool) -> Self {
+        Self {
+            tiles: self
+                .tiles
+                .iter()
+                .filter(|(id, entry)| predicate(id, entry))
+                .map(|(id, entry)| (id.clone(), entry.clone()))
+                .collect(),
+            sprites: self.sprites.clone(),
+        }
+    }
+
+    /// Set the sprite source IDs of the catalog
+    #[must_use]
+    pub fn sprites(self, sprites: Vec<String>) -> Self {
+        Self { sprites, ..self }
+    }
+
+    /// Keep only the tile sources of the given kinds, and the sprites if requested
+    #[must_use]
+    pub fn filter_kinds(&self, kinds: &[SourceKind], sprites: bool) -> Self {
+        let mut catalog = self.filter(|_, entry| kinds.contains(&entry.kind));
+        if !sprites {
+            catalog.sprites.clear();
+        }
+        catalog
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SourceEntry {
+    pub kind: SourceKind,
     pub content_type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content_encoding: Option<String>,
@@ -173,16 +441,176 @@ pub struct SourceEntry {
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub attribution: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minzoom: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maxzoom: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub center: Option<Center>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vector_layers: Option<Vec<VectorLayer>>,
+    /// Whether the source uses the URL query parameters of the tile requests
+    #[serde(default)]
+    pub url_query: bool,
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
 
+    /// A source with the same tile at all zoom levels up to its maxzoom
+    #[derive(Debug, Clone)]
+    pub struct TestSource {
+        pub id: &'static str,
+        pub info: TileInfo,
+        pub maxzoom: u8,
+        pub layers: Option<Vec<String>>,
+        pub data: Vec<u8>,
+        pub cache_control: Option<String>,
+        pub rate_limit: Option<RateLimit>,
+    }
+
+    impl TestSource {
+        pub fn new(id: &'static str, format: Format, data: &[u8]) -> Self {
+            Self {
+                id,
+                info: TileInfo::new(format, Encoding::Uncompressed),
+                maxzoom: 30,
+                layers: None,
+                data: data.to_vec(),
+                cache_control: None,
+                rate_limit: None,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Source for TestSource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> TileJSON {
+            tilejson::tilejson! { tiles: vec![], name: self.id.to_string(), maxzoom: self.maxzoom }
+        }
+
+        fn get_kind(&self) -> SourceKind {
+            SourceKind::Mbtiles
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.info
+        }
+
+        fn clone_source(&self) -> Box<dyn Source> {
+            Box::new(self.clone())
+        }
+
+        fn is_valid_zoom(&self, zoom: u8) -> bool {
+            zoom <= self.maxzoom
+        }
+
+        fn support_url_query(&self) -> bool {
+            false
+        }
+
+        fn get_cache_control(&self) -> Option<String> {
+            self.cache_control.clone()
+        }
+
+        fn get_rate_limit(&self) -> Option<RateLimit> {
+            self.rate_limit
+        }
+
+        fn get_layers(&self) -> Option<&[String]> {
+            self.layers.as_deref()
+        }
+
+        async fn get_tile(&self, _xyz: &Xyz, _query: &Option<UrlQuery>) -> Result<TileData> {
+            Ok(TileData::new(self.data.clone()))
+        }
+    }
+
+    #[test]
+    fn test_filter_catalog_kinds() {
+        let entry = |kind| SourceEntry {
+            kind,
+            content_type: "application/x-protobuf".to_string(),
+            content_encoding: None,
+            name: None,
+            description: None,
+            attribution: None,
+            minzoom: None,
+            maxzoom: None,
+            bounds: None,
+            center: None,
+            vector_layers: None,
+            url_query: false,
+        };
+        let catalog = SourceCatalog {
+            tiles: BTreeMap::from([
+                ("a".to_string(), entry(SourceKind::PgTable)),
+                ("b".to_string(), entry(SourceKind::PgFunction)),
+                ("c".to_string(), entry(SourceKind::Mbtiles)),
+            ]),
+            sprites: vec!["s".to_string()],
+        };
+
+        let filtered = catalog.filter_kinds(&[SourceKind::PgTable, SourceKind::Mbtiles], false);
+        assert_eq!(filtered.tiles.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert!(filtered.sprites.is_empty());
+        let filtered = catalog.filter_kinds(&[], true);
+        assert!(filtered.tiles.is_empty());
+        assert_eq!(filtered.sprites, vec!["s".to_string()]);
+
+        assert_eq!(
+            SourceKind::parse("pg_function"),
+            Some(SourceKind::PgFunction)
+        );
+        assert_eq!(SourceKind::parse("sprite"), None);
+        assert_eq!(
+            serde_json::to_string(&entry(SourceKind::Pmtiles)).unwrap(),
+            r#"{"kind":"pmtiles","content_type":"application/x-protobuf","url_query":false}"#
+        );
+    }
+
     #[test]
     fn xyz_format() {
         let xyz = Xyz { z: 1, x: 2, y: 3 };
         assert_eq!(format!("{xyz}"), "1,2,3");
         assert_eq!(format!("{xyz:#}"), "1/2/3");
     }
+
+    #[test]
+    fn xyz_tiles() {
+        let xyz = |z, x, y| Xyz { z, x, y };
+        assert!(xyz(0, 0, 0).is_valid());
+        assert!(xyz(2, 3, 3).is_valid());
+        assert!(!xyz(2, 4, 0).is_valid());
+        assert!(!xyz(0, 0, 1).is_valid());
+        assert!(xyz(31, u32::MAX >> 1, 0).is_valid());
+        assert!(!xyz(31, u32::MAX, 0).is_valid());
+        assert!(!xyz(32, 0, 0).is_valid());
+
+        let assert_bounds = |xyz: Xyz, expected: [f64; 4]| {
+            let bounds = xyz.bounds();
+            let actual = [bounds.left, bounds.bottom, bounds.right, bounds.top];
+            for (a, e) in actual.iter().zip(expected) {
+                assert!((a - e).abs() < 1e-6, "{actual:?} != {expected:?}");
+            }
+        };
+        let max_lat = 85.051_128_78;
+        assert_bounds(xyz(0, 0, 0), [-180.0, -max_lat, 180.0, max_lat]);
+        assert_bounds(xyz(1, 1, 0), [0.0, 0.0, 180.0, max_lat]);
+        // Coordinates of invalid tiles must not overflow
+        let bounds = xyz(0, u32::MAX, u32::MAX).bounds();
+        assert!(bounds.left > 180.0 && bounds.top < -max_lat);
+
+        assert_eq!(Xyz::containing(1, 10.0, 10.0), xyz(1, 1, 0));
+        assert_eq!(Xyz::containing(3, -10.0, 35.0), xyz(3, 3, 3));
+        assert_eq!(Xyz::containing(2, 180.0, -90.0), xyz(2, 3, 3));
+        assert_eq!(Xyz::containing(0, -200.0, 90.0), xyz(0, 0, 0));
+    }
 }
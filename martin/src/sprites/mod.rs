@@ -4,6 +4,7 @@ use std::fmt::Debug;
 use std::path::PathBuf;
 
 use futures::future::try_join_all;
+use itertools::Itertools;
 use log::{info, warn};
 use spreet::fs::get_svg_input_paths;
 use spreet::resvg::tiny_skia::Pixmap;
@@ -56,7 +57,8 @@ pub fn resolve_sprites(config: &mut Option<FileConfigEnum>) -> Result<SpriteSour
     if let Some(sources) = cfg.sources {
         for (id, source) in sources {
             configs.insert(id.clone(), source.clone());
-            add_source(id, source.abs_path()?, &mut results);
+            let cache_control = source.get_cache_control();
+            add_source(id, source.abs_path()?, cache_control, &mut results);
         }
     };
 
@@ -70,7 +72,7 @@ pub fn resolve_sprites(config: &mut Option<FileConfigEnum>) -> Result<SpriteSour
                 continue;
             };
             directories.push(path.clone());
-            add_source(name.to_string_lossy().to_string(), path, &mut results);
+            add_source(name.to_string_lossy().to_string(), path, None, &mut results);
         }
     }
 
@@ -79,7 +81,12 @@ pub fn resolve_sprites(config: &mut Option<FileConfigEnum>) -> Result<SpriteSour
     Ok(results)
 }
 
-fn add_source(id: String, path: PathBuf, results: &mut SpriteSources) {
+fn add_source(
+    id: String,
+    path: PathBuf,
+    cache_control: Option<String>,
+    results: &mut SpriteSources,
+) {
     let disp_path = path.display();
     if path.is_file() {
         warn!("Ignoring non-directory sprite source {id} from {disp_path}");
@@ -91,7 +98,10 @@ fn add_source(id: String, path: PathBuf, results: &mut SpriteSources) {
             }
             Entry::Vacant(v) => {
                 info!("Configured sprite source {} from {disp_path}", v.key());
-                v.insert(SpriteSource { path });
+                v.insert(SpriteSource {
+                    path,
+                    cache_control,
+                });
             }
         }
     };
@@ -101,12 +111,27 @@ fn add_source(id: String, path: PathBuf, results: &mut SpriteSources) {
 pub struct SpriteSources(HashMap<String, SpriteSource>);
 
 impl SpriteSources {
+    /// IDs of all sprite sources, sorted
+    #[must_use]
+    pub fn get_ids(&self) -> Vec<String> {
+        self.0.keys().cloned().sorted().collect()
+    }
+
     pub fn get_sprite_source(&self, id: &str) -> Result<&SpriteSource, SpriteError> {
         self.0
             .get(id)
             .ok_or_else(|| SpriteError::SpriteNotFound(id.to_string()))
     }
 
+    /// The `Cache-Control` header values of the sprite sources in the "id1,id2,id3" list,
+    /// which may optionally end with "@2x"
+    pub fn get_cache_control(&self, ids: &str) -> Result<Vec<Option<String>>, SpriteError> {
+        let ids = ids.strip_suffix("@2x").unwrap_or(ids);
+        ids.split(',')
+            .map(|id| Ok(self.get_sprite_source(id)?.cache_control.clone()))
+            .collect()
+    }
+
     /// Given a list of IDs in a format "id1,id2,id3", return a spritesheet with them all.
     /// `ids` may optionally end with "@2x" to request a high-DPI spritesheet.
     pub async fn get_sprites(&self, ids: &str) -> Result<Spritesheet, SpriteError> {
@@ -126,6 +151,7 @@ impl SpriteSources {
 #[derive(Clone, Debug)]
 pub struct SpriteSource {
     path: PathBuf,
+    cache_control: Option<String>,
 }
 
 async fn parse_sprite(
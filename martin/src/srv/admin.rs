@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AllSources, Config};
+use crate::file_config::{FileConfigEnum, FileConfigSrc, FileError};
+use crate::mbtiles::MbtSource;
+use crate::pg::{FunctionInfo, PgConfig, TableInfo};
+use crate::pmtiles::PmtSource;
+use crate::source::{Source, SourceMember, Sources};
+use crate::utils::{BoolOrObject, OneOrMany};
+
+/// Configuration of a single source, as accepted and returned by the source management API.
+/// The value has the same shape as the corresponding source in the configuration file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceConfig {
+    Mbtiles(FileConfigSrc),
+    Pmtiles(FileConfigSrc),
+    /// A table source of the first `postgres` connection
+    Table(Box<TableInfo>),
+    /// A function source of the first `postgres` connection
+    Function(Box<FunctionInfo>),
+}
+
+/// A source configuration together with its ID
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AdminSource {
+    pub id: String,
+    #[serde(flatten)]
+    pub config: SourceConfig,
+}
+
+impl SourceConfig {
+    /// Find the configuration of a source in the resolved config
+    #[must_use]
+    pub fn find(sources: &AllSources, id: &str) -> Option<Self> {
+        let cfg = &sources.config;
+        if let Some(src) = file_source(cfg.mbtiles.as_ref(), id) {
+            return Some(Self::Mbtiles(src));
+        }
+        if let Some(src) = file_source(cfg.pmtiles.as_ref(), id) {
+            return Some(Self::Pmtiles(src));
+        }
+        cfg.postgres
+            .iter()
+            .flat_map(OneOrMany::iter)
+            .find_map(|pg| {
+                if let Some(info) = pg.tables.as_ref().and_then(|v| v.get(id)) {
+                    Some(Self::Table(Box::new(info.clone())))
+                } else {
+                    let info = pg.functions.as_ref().and_then(|v| v.get(id))?;
+                    Some(Self::Function(Box::new(info.clone())))
+                }
+            })
+    }
+}
+
+/// Check that a removed source will not come back after a reload or a restart,
+/// i.e. that it is configured individually, is not in one of the configured directories,
+/// and is not published automatically, and that no other source is built from it
+fn check_removable(sources: &AllSources, id: &str) -> actix_web::Result<()> {
+    let cfg = &sources.config;
+    if let Some(user) = find_user(cfg, id) {
+        return Err(ErrorConflict(format!(
+            "Source {id} is a member of the {user} source, and cannot be removed"
+        )));
+    }
+    let Some(config) = SourceConfig::find(sources, id) else {
+        return Err(ErrorConflict(format!(
+            "Source {id} is not configured individually, and cannot be removed"
+        )));
+    };
+    let is_discovered = match config {
+        SourceConfig::Mbtiles(src) => is_in_directory(cfg.mbtiles.as_ref(), &src),
+        SourceConfig::Pmtiles(src) => is_in_directory(cfg.pmtiles.as_ref(), &src),
+        SourceConfig::Table(_) | SourceConfig::Function(_) => cfg
+            .postgres
+            .iter()
+            .flat_map(OneOrMany::iter)
+            .filter(|pg| {
+                pg.tables.as_ref().map_or(false, |v| v.contains_key(id))
+                    || pg.functions.as_ref().map_or(false, |v| v.contains_key(id))
+            })
+            .any(|pg| !matches!(pg.auto_publish, None | Some(BoolOrObject::Bool(false)))),
+    };
+    if is_discovered {
+        Err(ErrorConflict(format!(
+            "Source {id} is discovered automatically, and cannot be removed. Change the configuration file instead."
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// The ID of a composite or fallback source that has the source as a member, if any
+fn find_user<'a>(cfg: &'a Config, id: &str) -> Option<&'a str> {
+    let composites =
+        cfg.composites.iter().flatten().find_map(|(user, members)| {
+            members.iter().any(|m| m.source_id() == id).then_some(user)
+        });
+    let fallbacks =
+        cfg.fallbacks.iter().flatten().find_map(|(user, members)| {
+            members.iter().any(|m| m.source_id() == id).then_some(user)
+        });
+    composites.or(fallbacks).map(String::as_str)
+}
+
+/// Check if the source file is in one of the directories configured in `paths`
+fn is_in_directory(cfg: Option<&FileConfigEnum>, src: &FileConfigSrc) -> bool {
+    let (Some(FileConfigEnum::Config(cfg)), Ok(path)) = (cfg, src.abs_path()) else {
+        return false;
+    };
+    cfg.paths.iter().flat_map(OneOrMany::iter).any(|dir| {
+        dir.canonicalize()
+            .map_or(false, |dir| path.parent() == Some(dir.as_path()))
+    })
+}
+
+fn file_source(cfg: Option<&FileConfigEnum>, id: &str) -> Option<FileConfigSrc> {
+    match cfg? {
+        FileConfigEnum::Config(cfg) => cfg.sources.as_ref()?.get(id).cloned(),
+        _ => None,
+    }
+}
+
+/// Create a source and add it to a copy of the sources and their config.
+/// The source ID is resolved the same way as for the configured sources,
+/// so it may be renamed if another source already uses it.
+pub async fn add_source(
+    current: &AllSources,
+    id: &str,
+    config: SourceConfig,
+) -> actix_web::Result<(String, AllSources)> {
+    let mut all = copy_sources(current);
+    let (id, sources) = match config {
+        SourceConfig::Mbtiles(src) => {
+            let (id, source) = create_file_source(&all, id, src.clone(), MbtSource::new_box)
+                .await
+                .map_err(ErrorBadRequest)?;
+            insert_file_source(&mut all.config.mbtiles, id.clone(), src);
+            (id.clone(), single_source(id, source))
+        }
+        SourceConfig::Pmtiles(src) => {
+            let (id, source) = create_file_source(&all, id, src.clone(), PmtSource::new_box)
+                .await
+                .map_err(ErrorBadRequest)?;
+            insert_file_source(&mut all.config.pmtiles, id.clone(), src);
+            (id.clone(), single_source(id, source))
+        }
+        SourceConfig::Table(info) => {
+            let tables = HashMap::from([(id.to_string(), *info)]);
+            create_pg_sources(&mut all, Some(tables), None).await?
+        }
+        SourceConfig::Function(info) => {
+            let functions = HashMap::from([(id.to_string(), *info)]);
+            create_pg_sources(&mut all, None, Some(functions)).await?
+        }
+    };
+    // The source may replace an existing one with the same resolved ID
+    all.sources.extend(sources);
+    info!("Added source {id}");
+    Ok((id, all))
+}
+
+/// Remove a source from a copy of the sources and their config.
+/// Returns `None` if there is no such source, or an error if it cannot be removed for good.
+pub fn remove_source(current: &AllSources, id: &str) -> actix_web::Result<Option<AllSources>> {
+    if current.sources.get_source(id).is_err() {
+        return Ok(None);
+    }
+    check_removable(current, id)?;
+    let mut all = copy_sources(current);
+    all.sources.remove(id);
+    remove_file_source(&mut all.config.mbtiles, id);
+    remove_file_source(&mut all.config.pmtiles, id);
+    for pg in all.config.postgres.iter_mut().flat_map(OneOrMany::iter_mut) {
+        if let Some(tables) = &mut pg.tables {
+            tables.remove(id);
+        }
+        if let Some(functions) = &mut pg.functions {
+            functions.remove(id);
+        }
+    }
+    all.id_resolver.remove(id);
+    info!("Removed source {id}");
+    Ok(Some(all))
+}
+
+/// Copy the sources with their own resolved IDs, so that the IDs resolved while changing the copy
+/// are not reserved if the change fails
+fn copy_sources(current: &AllSources) -> AllSources {
+    let mut all = current.clone();
+    all.id_resolver = current.id_resolver.snapshot();
+    all
+}
+
+fn single_source(id: String, source: Box<dyn Source>) -> Sources {
+    let mut sources = Sources::default();
+    sources.insert(id, source);
+    sources
+}
+
+async fn create_file_source<Fut>(
+    all: &AllSources,
+    id: &str,
+    src: FileConfigSrc,
+    create_source: impl FnOnce(String, FileConfigSrc) -> Fut,
+) -> Result<(String, Box<dyn Source>), FileError>
+where
+    Fut: std::future::Future<Output = Result<Box<dyn Source>, FileError>>,
+{
+    let can = src.abs_path()?;
+    if !can.is_file() {
+        return Err(FileError::InvalidSourceFilePath(id.to_string(), can));
+    }
+    let id = all
+        .id_resolver
+        .resolve(id, can.to_string_lossy().to_string());
+    info!("Configured source {id} from {}", can.display());
+    let source = create_source(id.clone(), src).await?;
+    Ok((id, source))
+}
+
+fn insert_file_source(cfg: &mut Option<FileConfigEnum>, id: String, src: FileConfigSrc) {
+    let mut file_cfg = cfg
+        .as_mut()
+        .map(FileConfigEnum::extract_file_config)
+        .unwrap_or_default();
+    file_cfg
+        .sources
+        .get_or_insert_with(HashMap::new)
+        .insert(id, src);
+    *cfg = Some(FileConfigEnum::Config(file_cfg));
+}
+
+fn remove_file_source(cfg: &mut Option<FileConfigEnum>, id: &str) {
+    if let Some(FileConfigEnum::Config(file_cfg)) = cfg {
+        if let Some(sources) = &mut file_cfg.sources {
+            sources.remove(id);
+            if sources.is_empty() {
+                file_cfg.sources = None;
+            }
+        }
+        if file_cfg.is_empty() && file_cfg.unrecognized.is_empty() {
+            *cfg = None;
+        }
+    }
+}
+
+/// Create table or function sources with the pool of the first `postgres` connection,
+/// and add their resolved configuration to it
+async fn create_pg_sources(
+    all: &mut AllSources,
+    tables: Option<HashMap<String, TableInfo>>,
+    functions: Option<HashMap<String, FunctionInfo>>,
+) -> actix_web::Result<(String, Sources)> {
+    let (Some(pg), Some(pool)) = (
+        all.config
+            .postgres
+            .as_mut()
+            .and_then(|v| v.iter_mut().next()),
+        all.pg_pools.first(),
+    ) else {
+        return Err(ErrorBadRequest("No PostgreSQL connection is configured"));
+    };
+
+    // Only create the requested source, without auto-publishing anything else
+    let mut cfg = PgConfig {
+        auto_publish: Some(BoolOrObject::Bool(false)),
+        tables,
+        functions,
+        ..pg.clone()
+    };
+    let sources = cfg
+        .resolve_with_pool(all.id_resolver.clone(), pool.clone())
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let Some((id, _)) = sources.iter().next() else {
+        return Err(ErrorNotFound(
+            "The table or function was not found in the database",
+        ));
+    };
+    let id = id.to_string();
+
+    let resolved_tables = cfg.tables.unwrap_or_default();
+    let resolved_functions = cfg.functions.unwrap_or_default();
+    pg.tables
+        .get_or_insert_with(HashMap::new)
+        .extend(resolved_tables);
+    pg.functions
+        .get_or_insert_with(HashMap::new)
+        .extend(resolved_functions);
+    Ok((id, sources))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use indoc::indoc;
+
+    use super::*;
+    use crate::config::tests::parse_cfg;
+
+    #[test]
+    fn test_parse_source_config() {
+        let cfg: SourceConfig = serde_json::from_str(r#"{"mbtiles": "/tmp/a.mbtiles"}"#).unwrap();
+        assert_eq!(
+            cfg,
+            SourceConfig::Mbtiles(FileConfigSrc::Path(PathBuf::from("/tmp/a.mbtiles")))
+        );
+        let cfg: SourceConfig =
+            serde_json::from_str(r#"{"function": {"schema": "public", "function": "get_tile"}}"#)
+                .unwrap();
+        let SourceConfig::Function(info) = cfg else {
+            panic!("expected a function source");
+        };
+        assert_eq!(info.function, "get_tile");
+        assert!(serde_json::from_str::<SourceConfig>(r#"{"sprites": "/tmp"}"#).is_err());
+    }
+
+    #[test]
+    fn test_composite_member() {
+        let cfg = parse_cfg(indoc! {"
+            composites:
+              basemap: [roads, {source: water, layer: lakes}]
+        "});
+        assert_eq!(find_user(&cfg, "roads"), Some("basemap"));
+        assert_eq!(find_user(&cfg, "water"), Some("basemap"));
+        assert_eq!(find_user(&cfg, "lakes"), None);
+        assert_eq!(find_user(&cfg, "basemap"), None);
+    }
+
+    #[test]
+    fn test_fallback_member() {
+        let cfg = parse_cfg(indoc! {"
+            fallbacks:
+              terrain: [{source: regional, minzoom: 8}, global]
+        "});
+        assert_eq!(find_user(&cfg, "regional"), Some("terrain"));
+        assert_eq!(find_user(&cfg, "global"), Some("terrain"));
+        assert_eq!(find_user(&cfg, "terrain"), None);
+    }
+
+    #[test]
+    fn test_file_source_config() {
+        let path = |v: &str| FileConfigSrc::Path(PathBuf::from(v));
+        let mut cfg = Some(FileConfigEnum::Path(PathBuf::from("/tiles")));
+        insert_file_source(&mut cfg, "a".to_string(), path("/tmp/a.mbtiles"));
+        let Some(FileConfigEnum::Config(file_cfg)) = &cfg else {
+            panic!("expected a file config object");
+        };
+        assert!(file_cfg.paths.is_some());
+        assert_eq!(file_source(cfg.as_ref(), "a"), Some(path("/tmp/a.mbtiles")));
+        assert_eq!(file_source(cfg.as_ref(), "b"), None);
+
+        let mut cfg = None;
+        insert_file_source(&mut cfg, "a".to_string(), path("/tmp/a.mbtiles"));
+        remove_file_source(&mut cfg, "a");
+        assert_eq!(cfg, None);
+    }
+
+    #[test]
+    fn test_is_in_directory() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../tests/fixtures/files");
+        let file = FileConfigSrc::Path(dir.join("world_cities.mbtiles"));
+        let mut cfg = Some(FileConfigEnum::Path(dir.clone()));
+        insert_file_source(&mut cfg, "cities".to_string(), file.clone());
+        assert!(is_in_directory(cfg.as_ref(), &file));
+
+        let mut cfg = Some(FileConfigEnum::Path(dir.join("../..")));
+        insert_file_source(&mut cfg, "cities".to_string(), file.clone());
+        assert!(!is_in_directory(cfg.as_ref(), &file));
+        assert!(!is_in_directory(None, &file));
+    }
+}
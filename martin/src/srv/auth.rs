@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+
+use actix_web::dev::Payload;
+use actix_web::error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::{Data, Query};
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use tilejson::Bounds;
+
+use crate::source::{SourceCatalog, Xyz};
+use crate::Error::JwtKeyError;
+
+/// Authentication of the tile, `TileJSON` and catalog requests. If configured,
+/// every request must pass an API key or a JWT token, either as a bearer token in the
+/// `Authorization` header, or in the `key` query parameter.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct AuthConfig {
+    /// Static API keys, each with its own access restrictions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<Vec<ApiKeyConfig>>,
+    /// Validation of the JWT tokens, whose claims may contain the access restrictions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    #[serde(flatten)]
+    pub scope: AuthScope,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct JwtConfig {
+    pub algorithm: JwtAlgorithm,
+    /// The shared secret for `HS256`, or the PEM-encoded RSA public key for `RS256`
+    pub key_file: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "RS256")]
+    Rs256,
+}
+
+/// Restrictions of what an API key or a JWT token may access. Unset fields are not restricted.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct AuthScope {
+    /// Source IDs that may be requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sources: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minzoom: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maxzoom: Option<u8>,
+    /// Only the tiles intersecting these bounds may be requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+}
+
+impl AuthScope {
+    #[must_use]
+    pub fn allows_source(&self, id: &str) -> bool {
+        self.sources
+            .as_ref()
+            .map_or(true, |sources| sources.iter().any(|v| v == id))
+    }
+
+    /// Check that all sources in a comma-separated list of IDs may be requested
+    pub fn check_sources(&self, source_ids: &str) -> actix_web::Result<()> {
+        match source_ids.split(',').find(|id| !self.allows_source(id)) {
+            Some(id) => Err(ErrorForbidden(format!(
+                "Access to source {id} is not allowed"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_tile(&self, xyz: Xyz) -> actix_web::Result<()> {
+        if !xyz.is_valid() {
+            return Err(ErrorBadRequest(format!("Invalid tile {xyz:#}")));
+        }
+        let zoom = xyz.z;
+        if self.minzoom.map_or(false, |v| zoom < v) || self.maxzoom.map_or(false, |v| zoom > v) {
+            return Err(ErrorForbidden(format!(
+                "Access to zoom {zoom} is not allowed"
+            )));
+        }
+        if let Some(bounds) = self.bounds {
+            let tile = xyz.bounds();
+            if tile.left >= bounds.right
+                || tile.right <= bounds.left
+                || tile.bottom >= bounds.top
+                || tile.top <= bounds.bottom
+            {
+                return Err(ErrorForbidden(format!(
+                    "Access to tile {xyz:#} is not allowed"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the sources that may not be requested from the catalog
+    #[must_use]
+    pub fn filter_catalog(&self, catalog: &SourceCatalog) -> SourceCatalog {
+        catalog.filter(|id, _| self.allows_source(id))
+    }
+}
+
+/// API keys and JWT validation loaded from the [`AuthConfig`]
+#[derive(Clone)]
+pub struct Auth {
+    keys: HashMap<String, AuthScope>,
+    jwt: Option<(DecodingKey, Validation)>,
+}
+
+impl Debug for Auth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Auth")
+            .field("keys", &self.keys.len())
+            .field("jwt", &self.jwt.as_ref().map(|(_, v)| &v.algorithms))
+            .finish()
+    }
+}
+
+impl Auth {
+    /// Load the JWT key file if needed. Returns `None` if authentication is not configured.
+    pub fn new(config: Option<&AuthConfig>) -> crate::Result<Option<Self>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+        let keys = config
+            .keys
+            .iter()
+            .flatten()
+            .map(|v| (v.key.clone(), v.scope.clone()))
+            .collect();
+        let jwt = match &config.jwt {
+            Some(cfg) => {
+                let on_err = |e: String| JwtKeyError(e, cfg.key_file.clone());
+                let data = std::fs::read(&cfg.key_file).map_err(|e| on_err(e.to_string()))?;
+                let (key, algorithm) = match cfg.algorithm {
+                    JwtAlgorithm::Hs256 => {
+                        // Ignore the trailing newline of the secret file
+                        let len = data
+                            .iter()
+                            .rposition(|v| !v.is_ascii_whitespace())
+                            .map_or(0, |i| i + 1);
+                        (DecodingKey::from_secret(&data[..len]), Algorithm::HS256)
+                    }
+                    JwtAlgorithm::Rs256 => (
+                        DecodingKey::from_rsa_pem(&data).map_err(|e| on_err(e.to_string()))?,
+                        Algorithm::RS256,
+                    ),
+                };
+                Some((key, Validation::new(algorithm)))
+            }
+            None => None,
+        };
+        Ok(Some(Self { keys, jwt }))
+    }
+
+    /// Find the access restrictions of an API key, or of a valid JWT token
+    fn authorize(&self, token: &str) -> actix_web::Result<AuthScope> {
+        if let Some(scope) = self.keys.get(token) {
+            return Ok(scope.clone());
+        }
+        if let Some((key, validation)) = &self.jwt {
+            if let Ok(data) = decode::<AuthScope>(token, key, validation) {
+                return Ok(data.claims);
+            }
+        }
+        Err(ErrorUnauthorized("Invalid API key or token"))
+    }
+
+    /// Identify the client of a valid API key or JWT token, e.g. to count its rate limit:
+    /// the key itself, or the `sub` claim of the token. Returns `None` for invalid tokens.
+    pub(crate) fn client_id(&self, token: &str) -> Option<String> {
+        if self.keys.contains_key(token) {
+            return Some(format!("key:{token}"));
+        }
+        let (key, validation) = self.jwt.as_ref()?;
+        let claims = decode::<SubjectClaim>(token, key, validation).ok()?.claims;
+        Some(format!("jwt:{}", claims.sub.as_deref().unwrap_or(token)))
+    }
+}
+
+#[derive(Deserialize)]
+struct SubjectClaim {
+    sub: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    key: Option<String>,
+}
+
+/// The API key or token from the `Authorization` header, or from the `key` query parameter
+pub(crate) fn get_token(req: &HttpRequest) -> Option<String> {
+    let header = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match header {
+        Some(token) => Some(token.to_string()),
+        None => Query::<KeyQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|q| q.into_inner().key),
+    }
+}
+
+/// Check if the authentication is configured, i.e. if the `key` query parameter is an API key
+pub(crate) fn is_auth_enabled(req: &HttpRequest) -> bool {
+    req.app_data::<Data<Option<Auth>>>()
+        .map_or(false, |v| v.is_some())
+}
+
+/// Extracts the access restrictions of the request, or rejects it if the authentication
+/// is configured and the request has no valid API key or token.
+impl FromRequest for AuthScope {
+    type Error = actix_web::Error;
+    type Future = Ready<actix_web::Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let auth = req
+            .app_data::<Data<Option<Auth>>>()
+            .and_then(|v| v.as_ref().as_ref());
+        ready(match auth {
+            None => Ok(Self::default()),
+            Some(auth) => match get_token(req) {
+                Some(token) => auth.authorize(&token),
+                None => Err(ErrorUnauthorized("Missing API key or token")),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use indoc::indoc;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    fn xyz(z: u8, x: u32, y: u32) -> Xyz {
+        Xyz { z, x, y }
+    }
+
+    fn auth(config: &str) -> Auth {
+        let config: AuthConfig = serde_yaml::from_str(config).unwrap();
+        Auth::new(Some(&config)).unwrap().unwrap()
+    }
+
+    async fn scope(auth: Option<Auth>, req: TestRequest) -> actix_web::Result<AuthScope> {
+        let req = req.app_data(Data::new(auth)).to_http_request();
+        AuthScope::extract(&req).await
+    }
+
+    #[test]
+    fn test_scope() {
+        let scope = AuthScope {
+            sources: Some(vec!["a".to_string(), "b".to_string()]),
+            minzoom: Some(2),
+            maxzoom: Some(10),
+            bounds: Some(Bounds::new(0.0, 0.0, 10.0, 10.0)),
+        };
+        assert!(scope.check_sources("a,b").is_ok());
+        assert!(scope.check_sources("a,c").is_err());
+        assert!(scope.check_tile(xyz(2, 2, 1)).is_ok());
+        assert!(scope.check_tile(xyz(1, 1, 0)).is_err());
+        assert!(scope.check_tile(xyz(11, 1024, 1023)).is_err());
+        // A tile to the west of the bounds
+        assert!(scope.check_tile(xyz(2, 1, 1)).is_err());
+        // A tile to the south of the bounds
+        assert!(scope.check_tile(xyz(2, 2, 2)).is_err());
+
+        assert!(AuthScope::default().check_sources("c").is_ok());
+        assert!(AuthScope::default().check_tile(xyz(20, 0, 0)).is_ok());
+        // Tiles outside of the tile grid are rejected before their bounds are checked
+        assert!(AuthScope::default().check_tile(xyz(1, 2, 0)).is_err());
+        assert!(scope.check_tile(xyz(3, u32::MAX, u32::MAX)).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_api_keys() {
+        let auth = auth(indoc! {"
+            keys:
+              - key: secret
+                sources: [a]
+                maxzoom: 5
+              - key: all
+        "});
+
+        let req = TestRequest::default();
+        assert_eq!(scope(None, req).await.unwrap(), AuthScope::default());
+        let req = TestRequest::default();
+        assert!(scope(Some(auth.clone()), req).await.is_err());
+
+        let req = TestRequest::default().insert_header((AUTHORIZATION, "Bearer secret"));
+        let value = scope(Some(auth.clone()), req).await.unwrap();
+        assert_eq!(value.sources, Some(vec!["a".to_string()]));
+        assert_eq!(value.maxzoom, Some(5));
+
+        let req = TestRequest::with_uri("/a/0/0/0?key=all");
+        assert_eq!(
+            scope(Some(auth.clone()), req).await.unwrap(),
+            AuthScope::default()
+        );
+        let req = TestRequest::with_uri("/a/0/0/0?key=wrong");
+        assert!(scope(Some(auth.clone()), req).await.is_err());
+
+        assert_eq!(auth.client_id("secret"), Some("key:secret".to_string()));
+        assert_eq!(auth.client_id("wrong"), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_jwt() {
+        let key_file = std::env::temp_dir().join("martin_test_jwt_secret");
+        std::fs::write(&key_file, "jwt-secret\n").unwrap();
+        let config = AuthConfig {
+            keys: None,
+            jwt: Some(JwtConfig {
+                algorithm: JwtAlgorithm::Hs256,
+                key_file,
+            }),
+        };
+        let auth = Auth::new(Some(&config)).unwrap();
+
+        let token = |claims, secret: &[u8]| {
+            let key = EncodingKey::from_secret(secret);
+            format!(
+                "Bearer {}",
+                encode(&Header::default(), &claims, &key).unwrap()
+            )
+        };
+        let claims = json!({"exp": 4_000_000_000_u64, "sources": ["a"], "bounds": [0, 0, 10, 10]});
+
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, token(claims.clone(), b"jwt-secret")));
+        let value = scope(auth.clone(), req).await.unwrap();
+        assert_eq!(value.sources, Some(vec!["a".to_string()]));
+        assert_eq!(value.bounds, Some(Bounds::new(0.0, 0.0, 10.0, 10.0)));
+
+        let req =
+            TestRequest::default().insert_header((AUTHORIZATION, token(claims.clone(), b"wrong")));
+        assert!(scope(auth.clone(), req).await.is_err());
+
+        let expired = json!({"exp": 1_000_000_000_u64});
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, token(expired.clone(), b"jwt-secret")));
+        assert!(scope(auth.clone(), req).await.is_err());
+
+        let auth = auth.unwrap();
+        let client_id = |claims, secret: &[u8]| {
+            let token = token(claims, secret);
+            auth.client_id(token.strip_prefix("Bearer ").unwrap())
+        };
+        let user = json!({"exp": 4_000_000_000_u64, "sub": "user"});
+        assert_eq!(client_id(user, b"jwt-secret"), Some("jwt:user".to_string()));
+        assert_eq!(client_id(claims, b"wrong"), None);
+        assert_eq!(client_id(expired, b"jwt-secret"), None);
+    }
+}
@@ -0,0 +1,243 @@
+use actix_web::error::ErrorBadRequest;
+use martin_tile_utils::Encoding;
+use serde::Deserialize;
+use tilejson::Bounds;
+
+use crate::source::Xyz;
+use crate::srv::scheme;
+
+/// The maximum number of tiles in a single batch request
+pub const BATCH_MAX_TILES: u64 = 10_000;
+
+/// The maximum number of tiles of a batch request that are fetched concurrently,
+/// so that a batch request does not exhaust the database connection pool
+pub const BATCH_CONCURRENCY: usize = 8;
+
+/// The body of a batch tile request. Tiles may be listed individually,
+/// as rectangular ranges of tile coordinates, or as a bounding box with a zoom range.
+/// All listed tiles are returned in the order of this request.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRequest {
+    /// Individual tiles, e.g. `3/4/2`
+    #[serde(default)]
+    pub tiles: Vec<String>,
+    /// Ranges of tile coordinates at a zoom level
+    #[serde(default)]
+    pub ranges: Vec<TileRange>,
+    /// All tiles intersecting the bounding box at zoom levels from `minzoom` to `maxzoom`
+    pub bounds: Option<Bounds>,
+    /// Minimum zoom level of the `bounds` tiles [default: 0]
+    pub minzoom: Option<u8>,
+    /// Maximum zoom level of the `bounds` tiles, required if `bounds` is set
+    pub maxzoom: Option<u8>,
+}
+
+/// Inclusive range of tile coordinates at a zoom level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct TileRange {
+    pub z: u8,
+    pub x: [u32; 2],
+    pub y: [u32; 2],
+}
+
+impl TileRange {
+    fn len(&self) -> u64 {
+        let len = |[min, max]: [u32; 2]| u64::from(max.saturating_sub(min)) + 1;
+        len(self.x) * len(self.y)
+    }
+
+    fn tiles(self) -> impl Iterator<Item = Xyz> {
+        let z = self.z;
+        (self.y[0]..=self.y[1])
+            .flat_map(move |y| (self.x[0]..=self.x[1]).map(move |x| Xyz { z, x, y }))
+    }
+
+    /// The range of tiles at the zoom level intersecting the bounds
+    fn from_bounds(bounds: Bounds, z: u8) -> Self {
+        let min = Xyz::containing(z, bounds.left, bounds.top);
+        let max = Xyz::containing(z, bounds.right, bounds.bottom);
+        Self {
+            z,
+            x: [min.x, max.x],
+            y: [min.y, max.y],
+        }
+    }
+
+    fn validate(&self) -> actix_web::Result<()> {
+        let max = (1_u64 << self.z.min(32)) - 1;
+        if self.z > 31
+            || self.x[0] > self.x[1]
+            || self.y[0] > self.y[1]
+            || u64::from(self.x[1]) > max
+            || u64::from(self.y[1]) > max
+        {
+            Err(ErrorBadRequest(format!("Invalid tile range {self:?}")))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl BatchRequest {
+    /// Get all requested tiles, or an error if the request is invalid or has too many tiles
+    pub fn get_tiles(&self) -> actix_web::Result<Vec<Xyz>> {
+        let mut ranges = self.ranges.clone();
+        if let Some(bounds) = self.bounds {
+            let maxzoom = self
+                .maxzoom
+                .ok_or_else(|| ErrorBadRequest("maxzoom is required with bounds"))?;
+            if maxzoom > 31 {
+                return Err(ErrorBadRequest(format!("Invalid maxzoom {maxzoom}")));
+            }
+            let minzoom = self.minzoom.unwrap_or_default();
+            ranges.extend((minzoom..=maxzoom).map(|z| TileRange::from_bounds(bounds, z)));
+        }
+
+        let too_many = || {
+            ErrorBadRequest(format!(
+                "Too many tiles requested, the maximum is {BATCH_MAX_TILES}"
+            ))
+        };
+        let mut count = u64::try_from(self.tiles.len()).unwrap_or(u64::MAX);
+        if count > BATCH_MAX_TILES {
+            return Err(too_many());
+        }
+        for range in &ranges {
+            range.validate()?;
+            count = count.saturating_add(range.len());
+            if count > BATCH_MAX_TILES {
+                return Err(too_many());
+            }
+        }
+
+        let mut tiles = Vec::with_capacity(usize::try_from(count).unwrap_or_default());
+        for tile in &self.tiles {
+            tiles.push(parse_tile(tile)?);
+        }
+        tiles.extend(ranges.into_iter().flat_map(TileRange::tiles));
+        Ok(tiles)
+    }
+}
+
+/// Parse a `z/x/y` tile, ignoring the extension and the scale of the `y` value,
+/// and check that the tile exists at its zoom level
+fn parse_tile(tile: &str) -> actix_web::Result<Xyz> {
+    let mut parts = tile.splitn(3, '/');
+    let xyz = (|| {
+        Some(Xyz {
+            z: parts.next()?.parse().ok()?,
+            x: parts.next()?.parse().ok()?,
+            y: scheme::parse_tile_y(parts.next()?)?,
+        })
+    })();
+    xyz.filter(Xyz::is_valid)
+        .ok_or_else(|| ErrorBadRequest(format!("Invalid tile {tile}")))
+}
+
+/// Encode a tile of a batch response. Each tile is preceded by a 14-byte header:
+/// zoom (`u8`), x and y (`u32`), encoding (`u8`), and data length (`u32`),
+/// all integers in big-endian order. Empty tiles have no data.
+#[must_use]
+pub fn encode_batch_tile(xyz: Xyz, encoding: Encoding, data: &[u8]) -> Vec<u8> {
+    let encoding: u8 = match encoding {
+        Encoding::Uncompressed | Encoding::Internal => 0,
+        Encoding::Gzip => 1,
+        Encoding::Zlib => 2,
+        Encoding::Brotli => 3,
+        Encoding::Zstd => 4,
+    };
+    let len = u32::try_from(data.len()).unwrap_or(u32::MAX);
+    let mut result = Vec::with_capacity(14 + data.len());
+    result.push(xyz.z);
+    result.extend_from_slice(&xyz.x.to_be_bytes());
+    result.extend_from_slice(&xyz.y.to_be_bytes());
+    result.push(encoding);
+    result.extend_from_slice(&len.to_be_bytes());
+    result.extend_from_slice(data);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    fn xyz(z: u8, x: u32, y: u32) -> Xyz {
+        Xyz { z, x, y }
+    }
+
+    #[test]
+    fn test_batch_tiles() {
+        let req: BatchRequest = serde_json::from_str(indoc! {r#"
+            {
+              "tiles": ["3/4/2", "0/0/0.pbf"],
+              "ranges": [{"z": 2, "x": [1, 2], "y": [0, 1]}]
+            }
+        "#})
+        .unwrap();
+        assert_eq!(
+            req.get_tiles().unwrap(),
+            vec![
+                xyz(3, 4, 2),
+                xyz(0, 0, 0),
+                xyz(2, 1, 0),
+                xyz(2, 2, 0),
+                xyz(2, 1, 1),
+                xyz(2, 2, 1),
+            ]
+        );
+
+        // Western Europe
+        let req = BatchRequest {
+            bounds: Some(Bounds::new(-10.0, 35.0, 20.0, 60.0)),
+            minzoom: Some(1),
+            maxzoom: Some(3),
+            ..Default::default()
+        };
+        let tiles = req.get_tiles().unwrap();
+        assert_eq!(&tiles[..2], &[xyz(1, 0, 0), xyz(1, 1, 0)]);
+        assert_eq!(&tiles[tiles.len() - 2..], &[xyz(3, 3, 3), xyz(3, 4, 3)]);
+        assert_eq!(tiles.len(), 2 + 2 + 4);
+
+        let invalid = [
+            r#"{"tiles": ["3/4"]}"#,
+            r#"{"tiles": ["1/2/0"]}"#,
+            r#"{"tiles": ["32/0/0"]}"#,
+            r#"{"tiles": ["31/4294967295/0"]}"#,
+            r#"{"ranges": [{"z": 1, "x": [0, 2], "y": [0, 0]}]}"#,
+            r#"{"ranges": [{"z": 20, "x": [0, 1000], "y": [0, 1000]}]}"#,
+            r#"{"bounds": [-10, 35, 20, 60]}"#,
+            r#"{"tile": ["0/0/0"]}"#,
+        ];
+        for body in invalid {
+            let req = serde_json::from_str::<BatchRequest>(body);
+            assert!(req.map_or(true, |v| v.get_tiles().is_err()), "{body}");
+        }
+
+        // The tile count of these ranges would overflow `u64`
+        let range = TileRange {
+            z: 31,
+            x: [0, u32::MAX >> 1],
+            y: [0, u32::MAX >> 1],
+        };
+        let req = BatchRequest {
+            ranges: vec![range; 5],
+            ..Default::default()
+        };
+        assert!(req.get_tiles().is_err());
+    }
+
+    #[test]
+    fn test_encode_batch_tile() {
+        assert_eq!(
+            encode_batch_tile(xyz(3, 4, 258), Encoding::Gzip, b"abc"),
+            vec![3, 0, 0, 0, 4, 0, 0, 1, 2, 1, 0, 0, 0, 3, b'a', b'b', b'c']
+        );
+        assert_eq!(
+            encode_batch_tile(xyz(0, 0, 0), Encoding::Uncompressed, b""),
+            vec![0; 14]
+        );
+    }
+}
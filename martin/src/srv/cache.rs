@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use itertools::Itertools;
+use martin_tile_utils::Encoding;
+use moka::future::Cache;
+use moka::policy::EvictionPolicy;
+use serde::{Deserialize, Serialize};
+
+use crate::source::{TileData, UrlQuery, Xyz};
+use crate::utils::LayerFilter;
+
+pub const CACHE_SIZE_MB_DEFAULT: u64 = 512;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct CacheConfig {
+    /// Maximum memory used by the cached tiles, in megabytes. Use 0 to disable caching.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_mb: Option<u64>,
+    /// Number of seconds a tile is kept in the cache. By default tiles are only evicted when the cache is full.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+    /// Give each source its own cache of `size_mb` instead of sharing one cache between all sources.
+    /// Composite, re-compressed, and layer-filtered tiles share one more cache of `size_mb`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_source: Option<bool>,
+}
+
+/// Identifies a cached tile: the source (or a comma-separated list of sources),
+/// tile coordinates, the URL query if the source uses it, the encoding
+/// of the tile if it was re-compressed for the client, and the requested vector tile layers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    source_id: String,
+    xyz: Xyz,
+    query: Option<Vec<(String, String)>>,
+    encoding: Option<Encoding>,
+    layers: Option<LayerFilter>,
+}
+
+impl CacheKey {
+    #[must_use]
+    pub fn new(source_id: &str, xyz: Xyz, query: &Option<UrlQuery>) -> Self {
+        Self {
+            source_id: source_id.to_string(),
+            xyz,
+            query: query.as_ref().map(|q| {
+                q.iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .sorted()
+                    .collect()
+            }),
+            encoding: None,
+            layers: None,
+        }
+    }
+
+    /// The key of the same tile, re-compressed with the given encoding
+    #[must_use]
+    pub fn encoding(self, encoding: Encoding) -> Self {
+        Self {
+            encoding: Some(encoding),
+            ..self
+        }
+    }
+
+    /// The key of the same tile with only the requested layers
+    #[must_use]
+    pub fn layers(self, layers: Option<LayerFilter>) -> Self {
+        Self { layers, ..self }
+    }
+
+    /// The ID of the source if this is a tile of a single source as returned by the source,
+    /// or `None` for the composite, re-compressed, and layer-filtered tiles
+    fn single_source(&self) -> Option<&str> {
+        let is_source =
+            self.encoding.is_none() && self.layers.is_none() && !self.source_id.contains(',');
+        is_source.then_some(self.source_id.as_str())
+    }
+}
+
+type TileDataCache = Cache<CacheKey, TileData>;
+
+#[derive(Debug)]
+enum Caches {
+    Global(TileDataCache),
+    /// The caches of each source, and the cache shared by the tiles derived from the sources' tiles,
+    /// so that the number of caches does not grow with the requested combinations of sources
+    PerSource {
+        config: CacheConfig,
+        sources: RwLock<HashMap<String, TileDataCache>>,
+        shared: TileDataCache,
+    },
+}
+
+/// In-memory tile cache with a memory budget, optional time to live, and LRU eviction.
+/// Cloning is cheap, all clones share the same cached data.
+#[derive(Debug, Clone, Default)]
+pub struct TileCache(Option<Arc<Caches>>);
+
+impl TileCache {
+    #[must_use]
+    pub fn new(config: Option<&CacheConfig>) -> Self {
+        Self(match config {
+            Some(cfg) if cfg.size_mb != Some(0) => {
+                Some(Arc::new(if cfg.per_source == Some(true) {
+                    Caches::PerSource {
+                        config: cfg.clone(),
+                        sources: RwLock::default(),
+                        shared: new_cache(cfg),
+                    }
+                } else {
+                    Caches::Global(new_cache(cfg))
+                }))
+            }
+            _ => None,
+        })
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Get a tile from the cache, or compute and store it with `init` if it is missing.
+    /// Concurrent requests for the same missing key wait for the single `init` call to complete.
+    pub async fn get_or_insert<F, E>(&self, key: CacheKey, init: F) -> Result<TileData, Arc<E>>
+    where
+        F: Future<Output = Result<TileData, E>>,
+        E: Send + Sync + 'static,
+    {
+        match self.get_cache(&key) {
+            Some(cache) => cache.try_get_with(key, init).await,
+            None => init.await.map_err(Arc::new),
+        }
+    }
+
+    /// Remove all cached tiles, e.g. after the sources were reloaded
+    pub fn invalidate_all(&self) {
+        match self.0.as_deref() {
+            Some(Caches::Global(cache)) => cache.invalidate_all(),
+            Some(Caches::PerSource {
+                sources, shared, ..
+            }) => {
+                sources.write().unwrap().clear();
+                shared.invalidate_all();
+            }
+            None => {}
+        }
+    }
+
+    fn get_cache(&self, key: &CacheKey) -> Option<TileDataCache> {
+        match self.0.as_deref()? {
+            Caches::Global(cache) => Some(cache.clone()),
+            Caches::PerSource {
+                config,
+                sources: caches,
+                shared,
+            } => {
+                let Some(source_id) = key.single_source() else {
+                    return Some(shared.clone());
+                };
+                if let Some(cache) = caches.read().unwrap().get(source_id) {
+                    return Some(cache.clone());
+                }
+                let mut caches = caches.write().unwrap();
+                let cache = caches
+                    .entry(source_id.to_string())
+                    .or_insert_with(|| new_cache(config));
+                Some(cache.clone())
+            }
+        }
+    }
+}
+
+fn new_cache(cfg: &CacheConfig) -> TileDataCache {
+    let size_mb = cfg.size_mb.unwrap_or(CACHE_SIZE_MB_DEFAULT);
+    let mut builder = Cache::builder()
+        .name("tiles")
+        .max_capacity(size_mb.saturating_mul(1024 * 1024))
+        .weigher(|key: &CacheKey, value: &TileData| {
+            let size =
+                key.source_id.len() + value.data.len() + value.etag.as_ref().map_or(0, String::len);
+            size.try_into().unwrap_or(u32::MAX)
+        })
+        .eviction_policy(EvictionPolicy::lru());
+    if let Some(ttl) = cfg.ttl {
+        builder = builder.time_to_live(Duration::from_secs(ttl));
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const XYZ: Xyz = Xyz { z: 1, x: 2, y: 3 };
+
+    async fn get(cache: &TileCache, key: CacheKey, calls: &AtomicUsize, data: &[u8]) -> TileData {
+        cache
+            .get_or_insert(key, async {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, std::io::Error>(TileData::new(data.to_vec()))
+            })
+            .await
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn disabled_cache() {
+        let calls = AtomicUsize::new(0);
+        for cache in [
+            TileCache::new(None),
+            TileCache::new(Some(&CacheConfig {
+                size_mb: Some(0),
+                ..Default::default()
+            })),
+        ] {
+            assert!(!cache.is_enabled());
+            get(&cache, CacheKey::new("src", XYZ, &None), &calls, b"a").await;
+            get(&cache, CacheKey::new("src", XYZ, &None), &calls, b"a").await;
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+
+    #[actix_rt::test]
+    async fn cached_tiles() {
+        for per_source in [None, Some(true)] {
+            let cache = TileCache::new(Some(&CacheConfig {
+                per_source,
+                ..Default::default()
+            }));
+            assert!(cache.is_enabled());
+            let calls = AtomicUsize::new(0);
+
+            let key = CacheKey::new("src", XYZ, &None);
+            assert_eq!(get(&cache, key.clone(), &calls, b"a").await.data, &b"a"[..]);
+            assert_eq!(get(&cache, key.clone(), &calls, b"b").await.data, &b"a"[..]);
+            assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+            let gzip = key.encoding(Encoding::Gzip);
+            assert_eq!(
+                get(&cache, gzip.clone(), &calls, b"c").await.data,
+                &b"c"[..]
+            );
+            assert_eq!(get(&cache, gzip, &calls, b"d").await.data, &b"c"[..]);
+            assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+            let other = CacheKey::new("other", XYZ, &None);
+            assert_eq!(get(&cache, other, &calls, b"e").await.data, &b"e"[..]);
+            assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+            cache.invalidate_all();
+            let key = CacheKey::new("src", XYZ, &None);
+            assert_eq!(get(&cache, key, &calls, b"f").await.data, &b"f"[..]);
+            assert_eq!(calls.load(Ordering::Relaxed), 4);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn per_source_caches() {
+        let cache = TileCache::new(Some(&CacheConfig {
+            per_source: Some(true),
+            ..Default::default()
+        }));
+        let count = |cache: &TileCache| match cache.0.as_deref() {
+            Some(Caches::PerSource { sources, .. }) => sources.read().unwrap().len(),
+            _ => unreachable!(),
+        };
+        let calls = AtomicUsize::new(0);
+        get(&cache, CacheKey::new("a", XYZ, &None), &calls, b"a").await;
+        get(&cache, CacheKey::new("b", XYZ, &None), &calls, b"b").await;
+        assert_eq!(count(&cache), 2);
+        // Composite and re-compressed tiles do not get caches of their own
+        get(&cache, CacheKey::new("a,b", XYZ, &None), &calls, b"c").await;
+        get(&cache, CacheKey::new("b,a", XYZ, &None), &calls, b"d").await;
+        let gzip = CacheKey::new("a", XYZ, &None).encoding(Encoding::Gzip);
+        assert_eq!(
+            get(&cache, gzip.clone(), &calls, b"e").await.data,
+            &b"e"[..]
+        );
+        assert_eq!(get(&cache, gzip, &calls, b"f").await.data, &b"e"[..]);
+        assert_eq!(count(&cache), 2);
+        assert_eq!(calls.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn query_key() {
+        let query = |v: &[(&str, &str)]| {
+            Some(
+                v.iter()
+                    .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                    .collect::<UrlQuery>(),
+            )
+        };
+        assert_eq!(
+            CacheKey::new("src", XYZ, &query(&[("a", "1"), ("b", "2")])),
+            CacheKey::new("src", XYZ, &query(&[("b", "2"), ("a", "1")])),
+        );
+        assert_ne!(
+            CacheKey::new("src", XYZ, &query(&[("a", "1")])),
+            CacheKey::new("src", XYZ, &query(&[("a", "2")])),
+        );
+        assert_ne!(
+            CacheKey::new("src", XYZ, &None),
+            CacheKey::new("src", XYZ, &query(&[])),
+        );
+    }
+}
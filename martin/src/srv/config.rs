@@ -1,5 +1,10 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 
+use crate::srv::{AuthConfig, CacheConfig, CorsConfig, RateLimitConfig};
+use crate::utils::{deserialize_cache_control, BoolOrObject};
+
 pub const KEEP_ALIVE_DEFAULT: u64 = 75;
 pub const LISTEN_ADDRESSES_DEFAULT: &str = "0.0.0.0:3000";
 
@@ -11,14 +16,54 @@ pub struct SrvConfig {
     pub listen_addresses: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub worker_processes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    /// Bearer token required by the administrative endpoints like `POST /reload`, which are disabled if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    /// Default `Cache-Control` header value of the tile, `TileJSON` and sprite responses, e.g. `public, max-age=3600`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_cache_control"
+    )]
+    pub cache_control: Option<String>,
+    /// Default number of seconds the responses may be cached by the clients, a shortcut for `cache_control: public, max-age=...`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+    /// Path to a PEM file with the TLS certificate chain. HTTPS is used instead of HTTP if this and `tls_key` are set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to a PEM file with the private key of the TLS certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+    /// Require an API key or a JWT token to access the tiles, `TileJSON` and the catalog
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    /// Cross-origin resource sharing policy, or `false` to disable CORS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cors: Option<BoolOrObject<CorsConfig>>,
+    /// Limit the rate of the tile requests, e.g. per client IP address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Serve all endpoints under this path prefix, e.g. `/tiles`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_path: Option<String>,
+    /// The URL of this server as seen by the clients, e.g. `https://example.org/tiles`, used to build
+    /// the `TileJSON` and style URLs instead of the request host and the `x-rewrite-url` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
     use indoc::indoc;
+    use tilejson::Bounds;
 
     use super::*;
+    use crate::srv::{ApiKeyConfig, AuthScope, JwtAlgorithm, JwtConfig, RateLimitKey};
     use crate::test_utils::some;
+    use crate::utils::OneOrMany;
 
     #[test]
     fn parse_empty_config() {
@@ -27,13 +72,86 @@ mod tests {
                 keep_alive: 75
                 listen_addresses: '0.0.0.0:3000'
                 worker_processes: 8
+                cache:
+                  size_mb: 256
+                  ttl: 3600
+                  per_source: true
+                admin_token: secret
+                cache_control: no-cache
+                max_age: 3600
+                tls_cert: /etc/ssl/cert.pem
+                tls_key: /etc/ssl/key.pem
+                auth:
+                  keys:
+                    - key: secret
+                      sources: [points]
+                      minzoom: 0
+                      maxzoom: 10
+                      bounds: [-10, -20, 10, 20]
+                  jwt:
+                    algorithm: RS256
+                    key_file: /etc/martin/jwt.pem
+                cors:
+                  origin: 'https://*.example.org'
+                  methods: [GET, HEAD]
+                  credentials: true
+                rate_limit:
+                  requests_per_second: 10
+                  burst: 50
+                  key: api_key
+                base_path: /tiles
+                public_url: https://example.org/tiles
             "})
             .unwrap(),
             SrvConfig {
                 keep_alive: Some(75),
                 listen_addresses: some("0.0.0.0:3000"),
                 worker_processes: Some(8),
+                cache: Some(CacheConfig {
+                    size_mb: Some(256),
+                    ttl: Some(3600),
+                    per_source: Some(true),
+                }),
+                admin_token: some("secret"),
+                cache_control: some("no-cache"),
+                max_age: Some(3600),
+                tls_cert: Some(PathBuf::from("/etc/ssl/cert.pem")),
+                tls_key: Some(PathBuf::from("/etc/ssl/key.pem")),
+                auth: Some(AuthConfig {
+                    keys: Some(vec![ApiKeyConfig {
+                        key: "secret".to_string(),
+                        scope: AuthScope {
+                            sources: Some(vec!["points".to_string()]),
+                            minzoom: Some(0),
+                            maxzoom: Some(10),
+                            bounds: Some(Bounds::new(-10.0, -20.0, 10.0, 20.0)),
+                        },
+                    }]),
+                    jwt: Some(JwtConfig {
+                        algorithm: JwtAlgorithm::Rs256,
+                        key_file: PathBuf::from("/etc/martin/jwt.pem"),
+                    }),
+                }),
+                cors: Some(BoolOrObject::Object(CorsConfig {
+                    origin: Some(OneOrMany::One("https://*.example.org".to_string())),
+                    methods: Some(vec!["GET".to_string(), "HEAD".to_string()]),
+                    credentials: Some(true),
+                    ..Default::default()
+                })),
+                rate_limit: Some(RateLimitConfig {
+                    requests_per_second: Some(10.0),
+                    burst: Some(50),
+                    key: Some(RateLimitKey::ApiKey),
+                }),
+                base_path: some("/tiles"),
+                public_url: some("https://example.org/tiles"),
             }
         );
     }
+
+    #[test]
+    fn parse_invalid_cache_control() {
+        assert!(serde_yaml::from_str::<SrvConfig>("cache_control: \"no-cache\\n\"").is_err());
+        assert!(serde_yaml::from_str::<SrvConfig>("cache_control: ''").is_err());
+    }
 }
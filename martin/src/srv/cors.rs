@@ -0,0 +1,178 @@
+use std::str::FromStr;
+
+use actix_cors::Cors;
+use actix_web::http::header::{HeaderName, ACCEPT, AUTHORIZATION};
+use actix_web::http::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{BoolOrObject, OneOrMany};
+use crate::Error::CorsConfigError;
+
+/// Cross-origin resource sharing policy. By default, any origin may send `GET` requests
+/// with the `Authorization` and `Accept` headers.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct CorsConfig {
+    /// Allowed origins, e.g. `https://example.org`, or `https://*.example.org` for all of its subdomains.
+    /// Use `*` to allow any origin. [default: *]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<OneOrMany<String>>,
+    /// Allowed request methods [default: GET]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub methods: Option<Vec<String>>,
+    /// Allowed request headers [default: Authorization, Accept]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<Vec<String>>,
+    /// Number of seconds the browsers may cache the preflight request results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<usize>,
+    /// Allow the requests with credentials, e.g. cookies, which requires listing the allowed origins [default: false]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<bool>,
+}
+
+impl CorsConfig {
+    /// Get the CORS policy of the server, or `None` if CORS is disabled.
+    /// Returns an error if the configured methods or headers are invalid,
+    /// or if the requests with credentials are allowed from any origin.
+    pub fn resolve(config: Option<&BoolOrObject<Self>>) -> crate::Result<Option<Self>> {
+        let cors = match config {
+            None | Some(BoolOrObject::Bool(true)) => Self::default(),
+            Some(BoolOrObject::Bool(false)) => return Ok(None),
+            Some(BoolOrObject::Object(cors)) => cors.clone(),
+        };
+        for method in cors.methods.iter().flatten() {
+            Method::from_str(method).map_err(|_| CorsConfigError(format!("method {method}")))?;
+        }
+        for header in cors.headers.iter().flatten() {
+            HeaderName::from_str(header)
+                .map_err(|_| CorsConfigError(format!("header {header}")))?;
+        }
+        if cors.credentials == Some(true) && cors.get_origins().iter().any(|v| v == "*") {
+            return Err(CorsConfigError(
+                "credentials cannot be allowed for any origin, list the allowed origins instead"
+                    .to_string(),
+            ));
+        }
+        Ok(Some(cors))
+    }
+
+    fn get_origins(&self) -> Vec<String> {
+        self.origin
+            .as_ref()
+            .map_or_else(|| vec!["*".to_string()], |v| v.iter().cloned().collect())
+    }
+
+    /// Create the CORS middleware. The methods and headers must be validated by [`Self::resolve`].
+    #[must_use]
+    pub fn make_cors(&self) -> Cors {
+        let origins = self.get_origins();
+        let mut cors = if origins.iter().any(|v| v == "*") {
+            Cors::default().allow_any_origin()
+        } else {
+            Cors::default().allowed_origin_fn(move |origin, _| {
+                origin.to_str().map_or(false, |origin| {
+                    origins.iter().any(|v| origin_matches(v, origin))
+                })
+            })
+        };
+
+        cors = match &self.methods {
+            Some(methods) => cors.allowed_methods(methods.iter().map(String::as_str)),
+            None => cors.allowed_methods(vec!["GET"]),
+        };
+        cors = match &self.headers {
+            Some(headers) => cors.allowed_headers(headers.iter().map(String::as_str)),
+            None => cors.allowed_headers(vec![AUTHORIZATION, ACCEPT]),
+        };
+        cors = cors.max_age(self.max_age);
+        if self.credentials == Some(true) {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+/// Match an origin against an allowed origin, which may contain a single `*` wildcard,
+/// e.g. `https://*.example.org` matches `https://a.example.org` but not `https://example.org`.
+/// Like the scheme and host of the origins, the match is case-insensitive.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let (pattern, origin) = (pattern.to_ascii_lowercase(), origin.to_ascii_lowercase());
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            origin.len() > prefix.len() + suffix.len()
+                && origin.starts_with(prefix)
+                && origin.ends_with(suffix)
+        }
+        None => pattern == origin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    #[test]
+    fn test_origin_matches() {
+        assert!(origin_matches("https://example.org", "https://example.org"));
+        assert!(!origin_matches("https://example.org", "http://example.org"));
+        assert!(origin_matches(
+            "https://*.example.org",
+            "https://a.example.org"
+        ));
+        assert!(origin_matches(
+            "https://*.example.org",
+            "https://a.b.example.org"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.org",
+            "https://example.org"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.org",
+            "https://a.example.com"
+        ));
+        assert!(origin_matches("https://Example.org", "https://example.ORG"));
+        assert!(origin_matches(
+            "https://*.Example.org",
+            "https://A.example.ORG"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_cors() {
+        assert_eq!(
+            CorsConfig::resolve(None).unwrap(),
+            Some(CorsConfig::default())
+        );
+        assert_eq!(
+            CorsConfig::resolve(Some(&BoolOrObject::Bool(false))).unwrap(),
+            None
+        );
+
+        let cfg: BoolOrObject<CorsConfig> = serde_yaml::from_str(indoc! {"
+            origin: [https://example.org, 'https://*.example.org']
+            methods: [GET, HEAD]
+            headers: [Authorization, X-Custom]
+            max_age: 3600
+            credentials: true
+        "})
+        .unwrap();
+        let cors = CorsConfig::resolve(Some(&cfg)).unwrap().unwrap();
+        assert_eq!(
+            cors.methods,
+            Some(vec!["GET".to_string(), "HEAD".to_string()])
+        );
+        assert_eq!(cors.max_age, Some(3600));
+
+        let cfg: BoolOrObject<CorsConfig> =
+            serde_yaml::from_str("headers: ['bad header']").unwrap();
+        assert!(CorsConfig::resolve(Some(&cfg)).is_err());
+        let cfg: BoolOrObject<CorsConfig> =
+            serde_yaml::from_str("origin: '*'\ncredentials: true").unwrap();
+        assert!(CorsConfig::resolve(Some(&cfg)).is_err());
+        let cfg: BoolOrObject<CorsConfig> = serde_yaml::from_str("credentials: true").unwrap();
+        assert!(CorsConfig::resolve(Some(&cfg)).is_err());
+    }
+}
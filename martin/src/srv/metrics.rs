@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use martin_tile_utils::Encoding;
+use prometheus::{
+    exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::pg::PgPool;
+
+const NAMESPACE: &str = "martin";
+
+/// The `source` label of the rejected requests and of the requests of unknown sources
+pub const UNKNOWN_SOURCE: &str = "unknown";
+
+/// The `source` label of the requests combining several sources, e.g. `/a,b/0/0/0`
+pub const COMPOSITE_SOURCE: &str = "composite";
+
+/// Prometheus metrics of the tile server, exported in the text format by the `/metrics` endpoint.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    duration: HistogramVec,
+    tile_size: HistogramVec,
+    recompressions: IntCounterVec,
+    pool_size: IntGaugeVec,
+    pool_available: IntGaugeVec,
+    pool_waiting: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn new() -> Self {
+        let requests = IntCounterVec::new(
+            opts(
+                "tile_requests_total",
+                "Number of tile requests by response status",
+            ),
+            &["source", "zoom", "status"],
+        )
+        .unwrap();
+        let duration = HistogramVec::new(
+            HistogramOpts::from(opts(
+                "tile_request_duration_seconds",
+                "Time spent serving tile requests",
+            ))
+            .buckets(exponential_buckets(0.001, 2.0, 15).unwrap()),
+            &["source", "zoom"],
+        )
+        .unwrap();
+        let tile_size = HistogramVec::new(
+            HistogramOpts::from(opts("tile_size_bytes", "Size of the returned tiles"))
+                .buckets(exponential_buckets(256.0, 4.0, 9).unwrap()),
+            &["source"],
+        )
+        .unwrap();
+        let recompressions = IntCounterVec::new(
+            opts(
+                "tile_recompressions_total",
+                "Number of tiles re-compressed to match the encodings accepted by the client",
+            ),
+            &["source", "from", "to"],
+        )
+        .unwrap();
+        let pool_size = IntGaugeVec::new(
+            opts(
+                "pg_pool_size",
+                "Number of connections in the PostgreSQL pool",
+            ),
+            &["pool"],
+        )
+        .unwrap();
+        let pool_available = IntGaugeVec::new(
+            opts(
+                "pg_pool_available",
+                "Number of idle connections in the PostgreSQL pool",
+            ),
+            &["pool"],
+        )
+        .unwrap();
+        let pool_waiting = IntGaugeVec::new(
+            opts(
+                "pg_pool_waiting",
+                "Number of requests waiting for a PostgreSQL pool connection",
+            ),
+            &["pool"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        registry.register(Box::new(tile_size.clone())).unwrap();
+        registry.register(Box::new(recompressions.clone())).unwrap();
+        registry.register(Box::new(pool_size.clone())).unwrap();
+        registry.register(Box::new(pool_available.clone())).unwrap();
+        registry.register(Box::new(pool_waiting.clone())).unwrap();
+
+        Self {
+            registry,
+            requests,
+            duration,
+            tile_size,
+            recompressions,
+            pool_size,
+            pool_available,
+            pool_waiting,
+        }
+    }
+
+    /// The `source` label of a request of existing sources: the source ID, or the same label
+    /// for all requests combining several sources, so that the number of labels is bounded
+    #[must_use]
+    pub fn source_label(source_ids: &str) -> &str {
+        if source_ids.contains(',') {
+            COMPOSITE_SOURCE
+        } else {
+            source_ids
+        }
+    }
+
+    /// Record a served tile request. `size` is the size of the response body, if any.
+    pub fn observe_tile(
+        &self,
+        source: &str,
+        zoom: u8,
+        status: StatusCode,
+        elapsed: Duration,
+        size: Option<usize>,
+    ) {
+        let zoom = zoom.to_string();
+        self.requests
+            .with_label_values(&[source, &zoom, status.as_str()])
+            .inc();
+        self.duration
+            .with_label_values(&[source, &zoom])
+            .observe(elapsed.as_secs_f64());
+        if let Some(size) = size {
+            #[allow(clippy::cast_precision_loss)]
+            self.tile_size
+                .with_label_values(&[source])
+                .observe(size as f64);
+        }
+    }
+
+    pub fn observe_recompression(&self, source: &str, from: Encoding, to: Encoding) {
+        self.recompressions
+            .with_label_values(&[source, encoding_label(from), encoding_label(to)])
+            .inc();
+    }
+
+    /// Encode all metrics in the Prometheus text format, refreshing the connection pool gauges
+    pub fn encode(&self, pools: &[PgPool]) -> prometheus::Result<String> {
+        for pool in pools {
+            let status = pool.status();
+            let id = [pool.get_id()];
+            self.pool_size
+                .with_label_values(&id)
+                .set(to_i64(status.size));
+            self.pool_available
+                .with_label_values(&id)
+                .set(to_i64(status.available));
+            self.pool_waiting
+                .with_label_values(&id)
+                .set(to_i64(status.waiting));
+        }
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+fn opts(name: &str, help: &str) -> Opts {
+    Opts::new(name, help).namespace(NAMESPACE)
+}
+
+fn encoding_label(encoding: Encoding) -> &'static str {
+    match encoding {
+        Encoding::Uncompressed | Encoding::Internal => "none",
+        Encoding::Gzip => "gzip",
+        Encoding::Zlib => "zlib",
+        Encoding::Brotli => "brotli",
+        Encoding::Zstd => "zstd",
+    }
+}
+
+fn to_i64(value: usize) -> i64 {
+    value.try_into().unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_metrics() {
+        let metrics = Metrics::new();
+        let elapsed = Duration::from_millis(5);
+        metrics.observe_tile("src", 3, StatusCode::OK, elapsed, Some(1000));
+        metrics.observe_tile("src", 3, StatusCode::NO_CONTENT, elapsed, None);
+        metrics.observe_tile("src", 3, StatusCode::NO_CONTENT, elapsed, None);
+        metrics.observe_recompression("src", Encoding::Gzip, Encoding::Brotli);
+
+        let text = metrics.encode(&[]).unwrap();
+        assert!(
+            text.contains(r#"martin_tile_requests_total{source="src",status="200",zoom="3"} 1"#)
+        );
+        assert!(
+            text.contains(r#"martin_tile_requests_total{source="src",status="204",zoom="3"} 2"#)
+        );
+        assert!(
+            text.contains(r#"martin_tile_request_duration_seconds_count{source="src",zoom="3"} 3"#)
+        );
+        assert!(text.contains(r#"martin_tile_size_bytes_sum{source="src"} 1000"#));
+        assert!(text.contains(
+            r#"martin_tile_recompressions_total{from="gzip",source="src",to="brotli"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_source_label() {
+        assert_eq!(Metrics::source_label("src"), "src");
+        assert_eq!(Metrics::source_label("b,a,c"), COMPOSITE_SOURCE);
+        assert_eq!(Metrics::source_label("a,b,a"), COMPOSITE_SOURCE);
+    }
+}
@@ -1,7 +1,29 @@
+mod admin;
+mod auth;
+mod batch;
+mod cache;
 mod config;
+mod cors;
+mod metrics;
+mod rate_limit;
+mod reload;
+mod scheme;
 mod server;
+mod status;
+mod tls;
 
+pub use admin::{AdminSource, SourceConfig};
+pub use auth::{ApiKeyConfig, Auth, AuthConfig, AuthScope, JwtAlgorithm, JwtConfig};
+pub use batch::{encode_batch_tile, BatchRequest, TileRange, BATCH_CONCURRENCY, BATCH_MAX_TILES};
+pub use cache::{CacheConfig, CacheKey, TileCache, CACHE_SIZE_MB_DEFAULT};
 pub use config::{SrvConfig, KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT};
-pub use server::{new_server, router, RESERVED_KEYWORDS};
+pub use cors::CorsConfig;
+pub use metrics::{Metrics, UNKNOWN_SOURCE};
+pub use rate_limit::{RateLimit, RateLimitConfig, RateLimitKey, RateLimiter, RateLimiting};
+pub use reload::{SharedSources, SourcesLoader};
+pub use scheme::TileScheme;
+pub use server::{merge_tilejson, merge_tiles, new_server, router, RESERVED_KEYWORDS};
+pub use status::{check_status, HealthStatus, PoolStatus, ServerStatus, SourceStatus, StartTime};
+pub use tls::TlsCertResolver;
 
 pub use crate::source::SourceEntry;
@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::source::Source;
+use crate::srv::auth::{get_token, Auth};
+use crate::srv::SharedSources;
+
+/// The maximum number of buckets kept in memory, i.e. of clients and sources being limited
+const MAX_BUCKETS: usize = 100_000;
+
+/// The longest time a client is asked to wait before retrying
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Token bucket limit: each request takes a token, and the tokens are refilled at a constant rate
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RateLimit {
+    /// Number of tokens added per second
+    #[serde(deserialize_with = "deserialize_rate")]
+    pub requests_per_second: f64,
+    /// Maximum number of tokens, i.e. of requests allowed at once [default: `requests_per_second`]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_burst"
+    )]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    fn capacity(&self) -> f64 {
+        self.burst
+            .map_or(self.requests_per_second.max(1.0), f64::from)
+    }
+
+    /// The limit with the lowest rate, then the smallest burst, of the given ones if any
+    #[must_use]
+    pub fn most_restrictive(limits: impl IntoIterator<Item = Option<Self>>) -> Option<Self> {
+        limits.into_iter().flatten().min_by(|a, b| {
+            a.requests_per_second
+                .total_cmp(&b.requests_per_second)
+                .then(a.capacity().total_cmp(&b.capacity()))
+        })
+    }
+}
+
+/// Server-wide tile request rate limit
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct RateLimitConfig {
+    /// Number of tile requests allowed per second. If not set, only the sources with their own `rate_limit` are limited.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_opt_rate"
+    )]
+    pub requests_per_second: Option<f64>,
+    /// Maximum number of tile requests allowed at once [default: `requests_per_second`]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_burst"
+    )]
+    pub burst: Option<u32>,
+    /// What the limits are counted for [default: ip]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<RateLimitKey>,
+}
+
+fn deserialize_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "requests_per_second must be a positive number, got {value}"
+        )))
+    }
+}
+
+fn deserialize_burst<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let value = Option::<u32>::deserialize(deserializer)?;
+    if value == Some(0) {
+        Err(serde::de::Error::custom(
+            "burst must be a positive number, got 0",
+        ))
+    } else {
+        Ok(value)
+    }
+}
+
+fn deserialize_opt_rate<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Rate(#[serde(deserialize_with = "deserialize_rate")] f64);
+    Ok(Option::<Rate>::deserialize(deserializer)?.map(|v| v.0))
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The IP address of the client connection
+    #[default]
+    Ip,
+    /// The client IP address from the `Forwarded` or `X-Forwarded-For` headers set by a reverse proxy
+    ForwardedIp,
+    /// The valid API key or the subject of the valid token of the request, or the IP address otherwise
+    ApiKey,
+    /// The requested source, shared by all clients
+    Source,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(limit.capacity());
+        self.updated = now;
+    }
+}
+
+/// The client, the source, and the kind of the limit of a bucket
+type BucketKey = (String, String, RateLimitKind);
+
+/// The buckets in use, approximately the least recently used ones. New buckets are added to
+/// the current generation. When it is full, the previous generation is dropped with the buckets
+/// that have not been used since, so that the old buckets are forgotten without scanning them.
+#[derive(Debug)]
+struct Buckets {
+    current: HashMap<BucketKey, Bucket>,
+    previous: HashMap<BucketKey, Bucket>,
+    /// Maximum number of buckets in each generation
+    max: usize,
+}
+
+impl Buckets {
+    fn new(max: usize) -> Self {
+        Self {
+            current: HashMap::new(),
+            previous: HashMap::new(),
+            max,
+        }
+    }
+
+    /// Get the bucket, moving it to the current generation, or create a new one
+    fn get_or_insert(&mut self, key: &BucketKey, new: impl FnOnce() -> Bucket) -> &mut Bucket {
+        if !self.current.contains_key(key) {
+            let bucket = self.previous.remove(key).unwrap_or_else(new);
+            if self.current.len() >= self.max {
+                self.previous = std::mem::take(&mut self.current);
+            }
+            self.current.insert(key.clone(), bucket);
+        }
+        self.current.get_mut(key).expect("bucket was just inserted")
+    }
+
+    fn get_mut(&mut self, key: &BucketKey) -> Option<&mut Bucket> {
+        match self.current.get_mut(key) {
+            Some(bucket) => Some(bucket),
+            None => self.previous.get_mut(key),
+        }
+    }
+}
+
+/// Token buckets of all clients and sources, shared by all server workers
+#[derive(Debug)]
+pub struct RateLimiter {
+    default: Option<RateLimit>,
+    key: RateLimitKey,
+    buckets: Mutex<Buckets>,
+}
+
+/// The buckets of the server-wide and of the per-source limits are kept separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RateLimitKind {
+    Server,
+    Source,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(config: Option<&RateLimitConfig>) -> Self {
+        let config = config.cloned().unwrap_or_default();
+        Self {
+            default: config
+                .requests_per_second
+                .map(|requests_per_second| RateLimit {
+                    requests_per_second,
+                    burst: config.burst,
+                }),
+            key: config.key.unwrap_or_default(),
+            buckets: Mutex::new(Buckets::new(MAX_BUCKETS / 2)),
+        }
+    }
+
+    /// Take `count` tokens from the bucket of each requested source. Sources with their own limit
+    /// have a separate bucket for each client. Returns the time to wait before retrying
+    /// if any of the buckets has too few tokens. Requests of more tokens than the bucket can
+    /// hold only need a full bucket, and leave it in debt for the following requests.
+    fn check(
+        &self,
+        client: &str,
+        sources: &[(&str, Option<RateLimit>)],
+        count: u32,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("rate limiter lock is poisoned");
+
+        // All clients share the same buckets if the limits are counted per source
+        let client = if self.key == RateLimitKey::Source {
+            ""
+        } else {
+            client
+        };
+        let mut limits = Vec::new();
+        let mut add = |key: BucketKey, limit: RateLimit| {
+            if !limits.iter().any(|(k, _)| k == &key) {
+                limits.push((key, limit));
+            }
+        };
+        for (id, limit) in sources {
+            let id = (*id).to_string();
+            match (limit, self.default) {
+                (Some(limit), _) => add((client.to_string(), id, RateLimitKind::Source), *limit),
+                (None, Some(limit)) if self.key == RateLimitKey::Source => {
+                    add((String::new(), id, RateLimitKind::Server), limit);
+                }
+                (None, Some(limit)) => {
+                    add(
+                        (client.to_string(), String::new(), RateLimitKind::Server),
+                        limit,
+                    );
+                }
+                (None, None) => {}
+            }
+        }
+
+        // Only take the tokens if all buckets have them
+        let mut retry_after = Duration::ZERO;
+        for (key, limit) in &limits {
+            let bucket = buckets.get_or_insert(key, || Bucket {
+                tokens: limit.capacity(),
+                updated: now,
+            });
+            bucket.refill(*limit, now);
+            let required = f64::from(count).min(limit.capacity());
+            if bucket.tokens < required {
+                let wait = (required - bucket.tokens) / limit.requests_per_second;
+                retry_after = retry_after.max(retry_duration(wait));
+            }
+        }
+        if retry_after > Duration::ZERO {
+            return Err(retry_after);
+        }
+        for (key, _) in &limits {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= f64::from(count);
+            }
+        }
+        Ok(())
+    }
+
+    fn client_key(&self, req: &HttpRequest) -> String {
+        let info = req.connection_info();
+        let ip = match self.key {
+            RateLimitKey::ForwardedIp => info.realip_remote_addr(),
+            _ => info.peer_addr(),
+        };
+        let ip = ip.unwrap_or_default().to_string();
+        match self.key {
+            // Only valid keys are counted separately, so that random keys cannot bypass the limit
+            RateLimitKey::ApiKey => {
+                let auth = req
+                    .app_data::<Data<Option<Auth>>>()
+                    .and_then(|v| v.as_ref().as_ref());
+                auth.zip(get_token(req))
+                    .and_then(|(auth, token)| auth.client_id(&token))
+                    .unwrap_or(ip)
+            }
+            _ => ip,
+        }
+    }
+}
+
+/// The time to wait in seconds as a duration, capped in case the limit is not a positive number
+fn retry_duration(wait: f64) -> Duration {
+    if wait.is_finite() && wait < MAX_RETRY_AFTER.as_secs_f64() {
+        Duration::from_secs_f64(wait.max(0.0))
+    } else {
+        MAX_RETRY_AFTER
+    }
+}
+
+/// Middleware limiting the rate of the tile requests with the [`RateLimiter`] from the app data.
+/// Rejected requests get the `429 Too Many Requests` response with the `Retry-After` header.
+#[derive(Debug, Default)]
+pub struct RateLimiting;
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiting
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitingMiddleware { service }))
+    }
+}
+
+pub struct RateLimitingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(retry_after) = check_rate_limit(req.request(), 1) {
+            let response = too_many_requests(retry_after);
+            return Box::pin(ready(Ok(req.into_response(response).map_into_right_body())));
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+/// Take a token for each tile of a request that gets several tiles at once,
+/// and is therefore not limited by the [`RateLimiting`] middleware
+pub(crate) fn check_tile_count(req: &HttpRequest, count: usize) -> actix_web::Result<()> {
+    let count = u32::try_from(count).unwrap_or(u32::MAX);
+    check_rate_limit(req, count).map_err(|retry_after| {
+        InternalError::from_response("Too many requests", too_many_requests(retry_after)).into()
+    })
+}
+
+fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string()))
+        .body("Too many requests, please retry later")
+}
+
+fn check_rate_limit(req: &HttpRequest, count: u32) -> Result<(), Duration> {
+    let (Some(limiter), Some(sources)) = (
+        req.app_data::<Data<RateLimiter>>(),
+        req.app_data::<Data<SharedSources>>(),
+    ) else {
+        return Ok(());
+    };
+    let source_ids = req.match_info().get("source_ids").unwrap_or_default();
+    let sources = sources.load();
+    let limits: Vec<_> = source_ids
+        .split(',')
+        .map(|id| {
+            let limit = sources
+                .sources
+                .get_source(id)
+                .ok()
+                .and_then(Source::get_rate_limit);
+            (id, limit)
+        })
+        .collect();
+    limiter.check(&limiter.client_key(req), &limits, count, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_limiter(key: RateLimitKey) -> RateLimiter {
+        RateLimiter::new(Some(&RateLimitConfig {
+            requests_per_second: Some(2.0),
+            burst: Some(3),
+            key: Some(key),
+        }))
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = new_limiter(RateLimitKey::Ip);
+        let now = Instant::now();
+        let src = [("points", None)];
+        for _ in 0..3 {
+            assert!(limiter.check("1.1.1.1", &src, 1, now).is_ok());
+        }
+        let retry_after = limiter.check("1.1.1.1", &src, 1, now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(500));
+        // Other clients have their own buckets, shared by all sources
+        assert!(limiter.check("2.2.2.2", &src, 1, now).is_ok());
+        assert!(limiter
+            .check("1.1.1.1", &[("lines", None)], 1, now)
+            .is_err());
+        // A token is added every half a second
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.check("1.1.1.1", &src, 1, later).is_ok());
+        assert!(limiter.check("1.1.1.1", &src, 1, later).is_err());
+    }
+
+    #[test]
+    fn test_per_source_limit() {
+        let now = Instant::now();
+        let slow = Some(RateLimit {
+            requests_per_second: 0.5,
+            burst: Some(1),
+        });
+        let src = [("points", None), ("slow", slow)];
+
+        let limiter = new_limiter(RateLimitKey::Ip);
+        assert!(limiter.check("1.1.1.1", &src, 1, now).is_ok());
+        // The composite request fails without taking the tokens of the other source
+        let retry_after = limiter.check("1.1.1.1", &src, 1, now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(2));
+        assert!(limiter
+            .check("1.1.1.1", &[("points", None)], 1, now)
+            .is_ok());
+        assert!(limiter
+            .check("1.1.1.1", &[("points", None)], 1, now)
+            .is_ok());
+        assert!(limiter
+            .check("1.1.1.1", &[("points", None)], 1, now)
+            .is_err());
+        // Each client has its own bucket for the source with its own limit
+        assert!(limiter.check("2.2.2.2", &src, 1, now).is_ok());
+
+        // The buckets of each source are shared by all clients
+        let limiter = new_limiter(RateLimitKey::Source);
+        assert!(limiter.check("1.1.1.1", &src, 1, now).is_ok());
+        assert!(limiter.check("2.2.2.2", &src, 1, now).is_err());
+        assert!(limiter
+            .check("3.3.3.3", &[("points", None)], 1, now)
+            .is_ok());
+        assert!(limiter
+            .check("4.4.4.4", &[("points", None)], 1, now)
+            .is_ok());
+        assert!(limiter
+            .check("5.5.5.5", &[("points", None)], 1, now)
+            .is_err());
+        assert!(limiter.check("5.5.5.5", &[("lines", None)], 1, now).is_ok());
+
+        // Without the server-wide limit, only the sources with their own limit are limited
+        let limiter = RateLimiter::new(None);
+        for _ in 0..10 {
+            assert!(limiter
+                .check("1.1.1.1", &[("points", None)], 1, now)
+                .is_ok());
+        }
+        assert!(limiter.check("1.1.1.1", &[("slow", slow)], 1, now).is_ok());
+        assert!(limiter.check("1.1.1.1", &[("slow", slow)], 1, now).is_err());
+    }
+
+    #[test]
+    fn test_token_count() {
+        let limiter = new_limiter(RateLimitKey::Ip);
+        let now = Instant::now();
+        let src = [("points", None)];
+        assert!(limiter.check("1.1.1.1", &src, 2, now).is_ok());
+        assert!(limiter.check("1.1.1.1", &src, 2, now).is_err());
+        assert!(limiter.check("1.1.1.1", &src, 1, now).is_ok());
+        // A request of more tokens than the burst waits for the full bucket, and leaves it in debt
+        let retry_after = limiter.check("1.1.1.1", &src, 100, now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(1500));
+        let later = now + Duration::from_millis(1500);
+        assert!(limiter.check("1.1.1.1", &src, 100, later).is_ok());
+        let retry_after = limiter.check("1.1.1.1", &src, 1, later).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(49));
+    }
+
+    #[test]
+    fn test_bucket_generations() {
+        let now = Instant::now();
+        let key = |client: &str| (client.to_string(), String::new(), RateLimitKind::Server);
+        let new = || Bucket {
+            tokens: 1.0,
+            updated: now,
+        };
+        let mut buckets = Buckets::new(2);
+        buckets.get_or_insert(&key("a"), new).tokens = 0.0;
+        buckets.get_or_insert(&key("b"), new);
+        // A full generation becomes the previous one, and its used buckets are kept
+        buckets.get_or_insert(&key("c"), new);
+        assert!(buckets.get_or_insert(&key("a"), new).tokens < 1.0);
+        // The buckets unused for a whole generation are forgotten
+        buckets.get_or_insert(&key("d"), new);
+        assert!(buckets.get_mut(&key("b")).is_none());
+        assert!(buckets.get_mut(&key("a")).is_some());
+        assert_eq!(buckets.current.len() + buckets.previous.len(), 3);
+    }
+
+    #[test]
+    fn test_invalid_rate() {
+        let parse = |v: &str| serde_yaml::from_str::<RateLimitConfig>(v);
+        assert_eq!(
+            parse("requests_per_second: 2.5")
+                .unwrap()
+                .requests_per_second,
+            Some(2.5)
+        );
+        assert_eq!(parse("burst: 5").unwrap().requests_per_second, None);
+        assert!(parse("burst: 0").is_err());
+        assert!(serde_yaml::from_str::<RateLimit>("requests_per_second: 1\nburst: 0").is_err());
+        assert!(parse("requests_per_second: 0").is_err());
+        assert!(parse("requests_per_second: -1").is_err());
+        assert!(parse("requests_per_second: .inf").is_err());
+        assert!(parse("requests_per_second: .nan").is_err());
+        assert!(serde_yaml::from_str::<RateLimit>("requests_per_second: 0").is_err());
+
+        // Limits created in code are not validated, so the waiting time is capped
+        let limiter = RateLimiter::new(None);
+        let zero = Some(RateLimit {
+            requests_per_second: 0.0,
+            burst: Some(1),
+        });
+        let now = Instant::now();
+        assert!(limiter.check("1.1.1.1", &[("zero", zero)], 1, now).is_ok());
+        let retry_after = limiter
+            .check("1.1.1.1", &[("zero", zero)], 1, now)
+            .unwrap_err();
+        assert_eq!(retry_after, MAX_RETRY_AFTER);
+        assert_eq!(retry_duration(-1.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use futures::future::LocalBoxFuture;
+use log::info;
+use tokio::sync::Mutex;
+
+use crate::config::AllSources;
+use crate::srv::admin::{self, SourceConfig};
+use crate::Result;
+
+/// Creates a new set of sources, e.g. by re-reading the configuration file and re-discovering all sources
+pub type SourcesLoader = Arc<dyn Fn() -> LocalBoxFuture<'static, Result<AllSources>> + Send + Sync>;
+
+/// All sources served by the server. They can be atomically replaced while the server is running,
+/// in which case the in-flight requests finish using the sources they have started with.
+pub struct SharedSources {
+    current: ArcSwap<AllSources>,
+    loader: Option<SourcesLoader>,
+    /// Reloads and source changes run one at a time
+    reloading: Mutex<()>,
+    /// The file the configuration is saved to after a source is added or removed
+    config_file: Option<PathBuf>,
+}
+
+impl Debug for SharedSources {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedSources")
+            .field("can_reload", &self.can_reload())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedSources {
+    #[must_use]
+    pub fn new(sources: AllSources, loader: Option<SourcesLoader>) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(sources),
+            loader,
+            reloading: Mutex::default(),
+            config_file: None,
+        }
+    }
+
+    /// Save the configuration to this file whenever a source is added or removed
+    #[must_use]
+    pub fn save_config(self, config_file: Option<PathBuf>) -> Self {
+        Self {
+            config_file,
+            ..self
+        }
+    }
+
+    /// Get the current sources. The returned value is not affected by the subsequent reloads.
+    #[must_use]
+    pub fn load(&self) -> Arc<AllSources> {
+        self.current.load_full()
+    }
+
+    #[must_use]
+    pub fn can_reload(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    /// Create a new set of sources with the loader, and replace the current ones with it.
+    /// Concurrent reloads run one at a time. On error the current sources are kept.
+    /// Returns `None` if the sources were created without a loader.
+    pub async fn reload(&self) -> Option<Result<Arc<AllSources>>> {
+        let loader = self.loader.as_ref()?;
+        let _guard = self.reloading.lock().await;
+        info!("Reloading sources");
+        Some(loader().await.map(|sources| {
+            let sources = Arc::new(sources);
+            self.current.store(sources.clone());
+            info!("Sources have been reloaded");
+            sources
+        }))
+    }
+
+    /// Create a new source, and add it to the current sources.
+    /// Returns the resolved source ID, which may differ from the requested one.
+    pub async fn add_source(&self, id: &str, config: SourceConfig) -> actix_web::Result<String> {
+        let _guard = self.reloading.lock().await;
+        let (id, sources) = admin::add_source(&self.load(), id, config).await?;
+        self.store(sources)?;
+        Ok(id)
+    }
+
+    /// Remove a source from the current sources. Returns `false` if there is no such source,
+    /// or an error if the source would be discovered again after a reload.
+    pub async fn remove_source(&self, id: &str) -> actix_web::Result<bool> {
+        let _guard = self.reloading.lock().await;
+        let Some(sources) = admin::remove_source(&self.load(), id)? else {
+            return Ok(false);
+        };
+        self.store(sources)?;
+        Ok(true)
+    }
+
+    /// Replace the current sources, saving their configuration first
+    fn store(&self, sources: AllSources) -> actix_web::Result<()> {
+        if let Some(file_name) = &self.config_file {
+            sources
+                .config
+                .save_to_file(file_name)
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+        }
+        self.current.store(Arc::new(sources));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::fonts::FontSources;
+    use crate::sprites::SpriteSources;
+    use crate::styles::StyleSources;
+    use crate::{Config, IdResolver, Sources};
+
+    fn new_sources() -> AllSources {
+        AllSources {
+            sources: Sources::default(),
+            sprites: SpriteSources::default(),
+            fonts: FontSources::default(),
+            styles: StyleSources::default(),
+            pg_pools: Vec::new(),
+            config: Config::default(),
+            id_resolver: IdResolver::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn reload_sources() {
+        let sources = SharedSources::new(new_sources(), None);
+        assert!(!sources.can_reload());
+        assert!(sources.reload().await.is_none());
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let loader_calls = calls.clone();
+        let loader: SourcesLoader = Arc::new(move || {
+            loader_calls.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async { Ok(new_sources()) })
+        });
+        let sources = SharedSources::new(new_sources(), Some(loader));
+        assert!(sources.can_reload());
+
+        let old = sources.load();
+        let new = sources.reload().await.unwrap().unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(!Arc::ptr_eq(&old, &new));
+        assert!(Arc::ptr_eq(&new, &sources.load()));
+    }
+}
@@ -0,0 +1,153 @@
+use martin_tile_utils::Format;
+use serde::Deserialize;
+
+use crate::source::Xyz;
+
+/// The highest zoom level that fits the tile coordinates into `u32`
+const MAX_ZOOM: u8 = 31;
+
+/// Tile URL scheme, selected with the `?scheme=` query of a `TileJSON` request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileScheme {
+    /// `/{source_ids}/{z}/{x}/{y}` with the `y` axis going south
+    #[default]
+    Xyz,
+    /// `/{source_ids}/tms/{z}/{x}/{y}` with the `y` axis going north
+    Tms,
+    /// `/{source_ids}/q/{quadkey}` with Bing Maps quadkeys
+    Quadkey,
+}
+
+impl TileScheme {
+    /// The tile URL template path, relative to the `TileJSON` URL
+    #[must_use]
+    pub fn tiles_path(self) -> &'static str {
+        match self {
+            Self::Xyz => "/{z}/{x}/{y}",
+            Self::Tms => "/tms/{z}/{x}/{y}",
+            Self::Quadkey => "/q/{quadkey}",
+        }
+    }
+
+    /// The `TileJSON` `scheme` value. Quadkey URLs have no `TileJSON` scheme,
+    /// but they address the same tiles as `xyz`.
+    #[must_use]
+    pub fn tilejson_scheme(self) -> &'static str {
+        match self {
+            Self::Xyz | Self::Quadkey => "xyz",
+            Self::Tms => "tms",
+        }
+    }
+}
+
+/// Strip the optional file extension and the `@2x` retina suffix of the last tile URL segment,
+/// e.g. `3@2x.png` becomes `3`. Returns `None` if the extension is not a known tile format.
+#[must_use]
+pub fn strip_tile_suffix(name: &str) -> Option<&str> {
+    let name = match name.split_once('.') {
+        Some((name, ext)) => {
+            Format::parse(ext)?;
+            name
+        }
+        None => name,
+    };
+    Some(match name.split_once('@') {
+        Some((name, scale)) => {
+            let scale = scale.strip_suffix('x')?;
+            if scale.is_empty() || !scale.bytes().all(|v| v.is_ascii_digit()) {
+                None?;
+            }
+            name
+        }
+        None => name,
+    })
+}
+
+/// Parse the last tile URL segment as the `y` coordinate, ignoring its extension and scale.
+#[must_use]
+pub fn parse_tile_y(name: &str) -> Option<u32> {
+    strip_tile_suffix(name)?.parse().ok()
+}
+
+/// Convert TMS tile coordinates to XYZ by flipping the `y` axis.
+/// Returns `None` if the coordinates are out of range for the zoom level.
+#[must_use]
+pub fn xyz_from_tms(z: u8, x: u32, y: u32) -> Option<Xyz> {
+    if z > MAX_ZOOM {
+        return None;
+    }
+    let max = (1_u32 << z) - 1;
+    if x > max || y > max {
+        return None;
+    }
+    Some(Xyz { z, x, y: max - y })
+}
+
+/// Convert a Bing Maps quadkey, e.g. `0231`, to XYZ tile coordinates.
+/// The length of the quadkey is the zoom level, so an empty quadkey is the zoom 0 tile.
+#[must_use]
+pub fn xyz_from_quadkey(quadkey: &str) -> Option<Xyz> {
+    let z = u8::try_from(quadkey.len())
+        .ok()
+        .filter(|z| *z <= MAX_ZOOM)?;
+    let (mut x, mut y) = (0, 0);
+    for digit in quadkey.bytes() {
+        let digit = match digit {
+            b'0'..=b'3' => u32::from(digit - b'0'),
+            _ => None?,
+        };
+        x = (x << 1) | (digit & 1);
+        y = (y << 1) | (digit >> 1);
+    }
+    Some(Xyz { z, x, y })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_tile_suffix() {
+        assert_eq!(strip_tile_suffix("3"), Some("3"));
+        assert_eq!(strip_tile_suffix("3.pbf"), Some("3"));
+        assert_eq!(strip_tile_suffix("3.mvt"), Some("3"));
+        assert_eq!(strip_tile_suffix("3@2x"), Some("3"));
+        assert_eq!(strip_tile_suffix("3@2x.png"), Some("3"));
+        assert_eq!(strip_tile_suffix("0231@3x.jpg"), Some("0231"));
+        assert_eq!(strip_tile_suffix("3.txt"), None);
+        assert_eq!(strip_tile_suffix("3@x.png"), None);
+        assert_eq!(strip_tile_suffix("3@2.png"), None);
+
+        assert_eq!(parse_tile_y("3@2x.png"), Some(3));
+        assert_eq!(parse_tile_y("a.png"), None);
+    }
+
+    #[test]
+    fn test_xyz_from_tms() {
+        assert_eq!(xyz_from_tms(0, 0, 0), Some(Xyz { z: 0, x: 0, y: 0 }));
+        assert_eq!(xyz_from_tms(2, 1, 0), Some(Xyz { z: 2, x: 1, y: 3 }));
+        assert_eq!(xyz_from_tms(2, 1, 3), Some(Xyz { z: 2, x: 1, y: 0 }));
+        assert_eq!(xyz_from_tms(2, 1, 4), None);
+        assert_eq!(xyz_from_tms(2, 4, 1), None);
+        assert_eq!(
+            xyz_from_tms(31, 0, 0),
+            Some(Xyz {
+                z: 31,
+                x: 0,
+                y: u32::MAX >> 1
+            })
+        );
+        assert_eq!(xyz_from_tms(32, 0, 0), None);
+    }
+
+    #[test]
+    fn test_xyz_from_quadkey() {
+        assert_eq!(xyz_from_quadkey(""), Some(Xyz { z: 0, x: 0, y: 0 }));
+        assert_eq!(xyz_from_quadkey("1"), Some(Xyz { z: 1, x: 1, y: 0 }));
+        assert_eq!(xyz_from_quadkey("2"), Some(Xyz { z: 1, x: 0, y: 1 }));
+        assert_eq!(xyz_from_quadkey("213"), Some(Xyz { z: 3, x: 3, y: 5 }));
+        assert_eq!(xyz_from_quadkey("214"), None);
+        assert_eq!(xyz_from_quadkey(&"3".repeat(32)), None);
+    }
+}
@@ -1,32 +1,55 @@
+use std::io;
+use std::io::Cursor;
 use std::string::ToString;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use actix_cors::Cors;
 use actix_http::ContentEncoding;
+use actix_web::body::{BodySize, MessageBody};
 use actix_web::dev::Server;
-use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound};
+use actix_web::error::{
+    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorNotImplemented,
+    ErrorUnauthorized,
+};
 use actix_web::http::header::{
-    AcceptEncoding, ContentType, Encoding as HeaderEnc, HeaderValue, Preference, CACHE_CONTROL,
-    CONTENT_ENCODING, AUTHORIZATION, ACCEPT
+    Accept, AcceptEncoding, ContentType, ETag, Encoding as HeaderEnc, EntityTag, HeaderValue,
+    HttpDate, IfModifiedSince, IfNoneMatch, LastModified, Preference, AUTHORIZATION, CACHE_CONTROL,
+    CONTENT_ENCODING, VARY,
 };
-use actix_web::http::Uri;
+use actix_web::http::{StatusCode, Uri};
 use actix_web::middleware::TrailingSlash;
-use actix_web::web::{Data, Path, Query};
+use actix_web::web::{Bytes, Data, Json, Path, Query};
 use actix_web::{
-    middleware, route, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
-    Result,
+    middleware, route, web, App, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder,
+    HttpServer, Result,
 };
 use futures::future::try_join_all;
-use log::error;
+use futures::{stream, StreamExt};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, ImageOutputFormat, RgbaImage};
+use log::{debug, error, info};
 use martin_tile_utils::{Encoding, Format, TileInfo};
+use prometheus::TEXT_FORMAT;
 use serde::Deserialize;
 use tilejson::{tilejson, TileJSON};
 
 use crate::config::AllSources;
-use crate::source::{Source, Sources, UrlQuery, Xyz};
-use crate::sprites::{SpriteError, SpriteSources};
+use crate::fonts::FontError;
+use crate::source::{
+    is_blendable, Source, SourceCatalog, SourceKind, Sources, TileData, UrlQuery, Xyz,
+};
+use crate::sprites::SpriteError;
+use crate::srv::auth::is_auth_enabled;
 use crate::srv::config::{SrvConfig, KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT};
-use crate::utils::{decode_brotli, decode_gzip, encode_brotli, encode_gzip};
+use crate::srv::rate_limit::check_tile_count;
+use crate::srv::scheme;
+use crate::srv::{
+    check_status, encode_batch_tile, AdminSource, Auth, AuthScope, BatchRequest, CacheKey,
+    CorsConfig, HealthStatus, Metrics, RateLimiter, RateLimiting, SharedSources, SourceConfig,
+    StartTime, TileCache, TileScheme, TlsCertResolver, BATCH_CONCURRENCY, UNKNOWN_SOURCE,
+};
+use crate::styles::StyleError;
+use crate::utils::{cache_control_value, most_restrictive_cache_control, recompress, LayerFilter};
 use crate::Error::BindingError;
 
 /// List of keywords that cannot be used as source IDs. Some of these are reserved for future use.
@@ -34,12 +57,14 @@ use crate::Error::BindingError;
 /// This list is documented in the `docs/src/using.md` file, which should be kept in sync.
 pub const RESERVED_KEYWORDS: &[&str] = &[
     "_", "catalog", "config", "font", "health", "help", "index", "manifest", "metrics", "refresh",
-    "reload", "sprite", "status",
+    "reload", "sprite", "status", "style",
 ];
 
 static SUPPORTED_ENCODINGS: &[HeaderEnc] = &[
     HeaderEnc::brotli(),
     HeaderEnc::gzip(),
+    HeaderEnc::zstd(),
+    HeaderEnc::deflate(),
     HeaderEnc::identity(),
 ];
 
@@ -49,11 +74,96 @@ struct TileJsonRequest {
 }
 
 #[derive(Deserialize)]
-struct TileRequest {
+struct HealthQuery {
+    deep: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct CatalogQuery {
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LayersQuery {
+    layers: Option<String>,
+    exclude_layers: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TileJsonQuery {
+    #[serde(default)]
+    scheme: TileScheme,
+}
+
+#[derive(Deserialize)]
+struct StyleRequest {
+    style_id: String,
+}
+
+#[derive(Deserialize)]
+struct FontRequest {
+    fontstack: String,
+    start: u32,
+    end: u32,
+}
+
+/// Tile path of the XYZ and TMS schemes. The `y` value may have an extension and a scale suffix.
+#[derive(Deserialize)]
+struct TilePath {
     source_ids: String,
     z: u8,
     x: u32,
-    y: u32,
+    y: String,
+}
+
+#[derive(Deserialize)]
+struct QuadkeyPath {
+    source_ids: String,
+    quadkey: String,
+}
+
+/// A tile request of any URL scheme, converted to XYZ coordinates
+struct TileRequest {
+    source_ids: String,
+    xyz: Xyz,
+}
+
+impl TileRequest {
+    fn from_xyz(path: TilePath) -> Result<Self> {
+        let y = scheme::parse_tile_y(&path.y).ok_or_else(|| invalid_tile_path(&path.y))?;
+        Ok(Self {
+            xyz: Xyz {
+                z: path.z,
+                x: path.x,
+                y,
+            },
+            source_ids: path.source_ids,
+        })
+    }
+
+    fn from_tms(path: TilePath) -> Result<Self> {
+        let xyz = scheme::parse_tile_y(&path.y)
+            .and_then(|y| scheme::xyz_from_tms(path.z, path.x, y))
+            .ok_or_else(|| invalid_tile_path(&format!("{}/{}/{}", path.z, path.x, path.y)))?;
+        Ok(Self {
+            source_ids: path.source_ids,
+            xyz,
+        })
+    }
+
+    fn from_quadkey(path: QuadkeyPath) -> Result<Self> {
+        let xyz = scheme::strip_tile_suffix(&path.quadkey)
+            .and_then(scheme::xyz_from_quadkey)
+            .ok_or_else(|| invalid_tile_path(&path.quadkey))?;
+        Ok(Self {
+            source_ids: path.source_ids,
+            xyz,
+        })
+    }
+}
+
+fn invalid_tile_path(path: &str) -> actix_web::Error {
+    ErrorNotFound(format!("Invalid tile path {path}"))
 }
 
 pub fn map_internal_error<T: std::fmt::Display>(e: T) -> actix_web::Error {
@@ -69,6 +179,23 @@ pub fn map_sprite_error(e: SpriteError) -> actix_web::Error {
     }
 }
 
+pub fn map_font_error(e: FontError) -> actix_web::Error {
+    #[allow(clippy::enum_glob_use)]
+    use FontError::*;
+    match e {
+        FontNotFound(_) => ErrorNotFound(e.to_string()),
+        InvalidFontRange(..) => ErrorBadRequest(e.to_string()),
+        FontParsingError(..) => map_internal_error(e),
+    }
+}
+
+pub fn map_style_error(e: StyleError) -> actix_web::Error {
+    match e {
+        StyleError::StyleNotFound(_) => ErrorNotFound(e.to_string()),
+        _ => map_internal_error(e),
+    }
+}
+
 /// Root path will eventually have a web front. For now, just a stub.
 #[route("/", method = "GET", method = "HEAD")]
 #[allow(clippy::unused_async)]
@@ -80,12 +207,45 @@ async fn get_index() -> &'static str {
 }
 
 /// Return 200 OK if healthy. Used for readiness and liveness probes.
+/// With `?deep=true`, all database connections and tile sources are checked too,
+/// and 503 is returned with the failed checks if any of them fail.
 #[route("/health", method = "GET", method = "HEAD")]
-#[allow(clippy::unused_async)]
-async fn get_health() -> impl Responder {
+async fn get_health(
+    query: Query<HealthQuery>,
+    sources: Data<SharedSources>,
+    start_time: Data<StartTime>,
+) -> HttpResponse {
+    if query.deep.unwrap_or_default() {
+        let scope = AuthScope::default();
+        let status = check_status(&sources.load(), start_time.0.elapsed(), &scope).await;
+        if status.status != HealthStatus::Ok {
+            return HttpResponse::ServiceUnavailable()
+                .insert_header((CACHE_CONTROL, "no-cache"))
+                .body(status.errors().join("\n"));
+        }
+    }
     HttpResponse::Ok()
         .insert_header((CACHE_CONTROL, "no-cache"))
-        .message_body("OK")
+        .body("OK")
+}
+
+/// Detailed status of the server, its database connections and each tile source
+/// the request may access. Returns 503 if any of the checks fail.
+#[route("/status", method = "GET", method = "HEAD")]
+async fn get_status(
+    sources: Data<SharedSources>,
+    start_time: Data<StartTime>,
+    scope: AuthScope,
+) -> HttpResponse {
+    let status = check_status(&sources.load(), start_time.0.elapsed(), &scope).await;
+    let mut builder = if status.status == HealthStatus::Ok {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    builder
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .json(status)
 }
 
 #[route(
@@ -95,20 +255,175 @@ async fn get_health() -> impl Responder {
     wrap = "middleware::Compress::default()"
 )]
 #[allow(clippy::unused_async)]
-async fn get_catalog(sources: Data<Sources>) -> impl Responder {
-    HttpResponse::Ok().json(sources.get_catalog())
+async fn get_catalog(
+    query: Query<CatalogQuery>,
+    sources: Data<SharedSources>,
+    scope: AuthScope,
+) -> Result<HttpResponse> {
+    let catalog = scope.filter_catalog(&get_full_catalog(&sources.load()));
+    let catalog = match &query.kind {
+        Some(kind) => {
+            let mut kinds = Vec::new();
+            let mut sprites = false;
+            for kind in kind.split(',') {
+                match kind {
+                    "sprite" => sprites = true,
+                    _ => kinds.push(
+                        SourceKind::parse(kind)
+                            .ok_or_else(|| ErrorBadRequest(format!("Unknown kind {kind}")))?,
+                    ),
+                }
+            }
+            catalog.filter_kinds(&kinds, sprites)
+        }
+        None => catalog,
+    };
+    Ok(HttpResponse::Ok().json(catalog))
+}
+
+/// The catalog of the tile sources together with the sprite sources
+fn get_full_catalog(sources: &AllSources) -> SourceCatalog {
+    sources
+        .sources
+        .get_catalog()
+        .clone()
+        .sprites(sources.sprites.get_ids())
+}
+
+#[route("/metrics", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_metrics(metrics: Data<Metrics>, sources: Data<SharedSources>) -> Result<HttpResponse> {
+    let text = metrics
+        .encode(&sources.load().pg_pools)
+        .map_err(map_internal_error)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .content_type(TEXT_FORMAT)
+        .body(text))
+}
+
+/// Re-read the configuration and replace all sources. The current sources are kept on error.
+#[route("/reload", method = "POST")]
+async fn post_reload(
+    req: HttpRequest,
+    config: Data<SrvConfig>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+) -> Result<HttpResponse> {
+    check_admin_token(&req, config.admin_token.as_deref())?;
+    match sources.reload().await {
+        Some(Ok(sources)) => {
+            cache.invalidate_all();
+            Ok(HttpResponse::Ok().json(get_full_catalog(&sources)))
+        }
+        Some(Err(e)) => Err(map_internal_error(e)),
+        None => Err(ErrorNotImplemented(
+            "Reloading is not supported by this server",
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct AdminSourcePath {
+    source_id: String,
+}
+
+/// Get the configuration of a source, in the same shape as accepted by [`post_admin_source`]
+#[route("/_/sources/{source_id}", method = "GET")]
+#[allow(clippy::unused_async)]
+async fn get_admin_source(
+    req: HttpRequest,
+    path: Path<AdminSourcePath>,
+    config: Data<SrvConfig>,
+    sources: Data<SharedSources>,
+) -> Result<HttpResponse> {
+    check_admin_token(&req, config.admin_token.as_deref())?;
+    let id = path.into_inner().source_id;
+    let config = SourceConfig::find(&sources.load(), &id)
+        .ok_or_else(|| ErrorNotFound(format!("Source {id} does not exist")))?;
+    Ok(HttpResponse::Ok().json(AdminSource { id, config }))
+}
+
+/// Create a new source and add it to the running server. The response contains the resolved ID,
+/// which differs from the requested one if it was already used by a different source.
+#[route("/_/sources/{source_id}", method = "POST")]
+async fn post_admin_source(
+    req: HttpRequest,
+    path: Path<AdminSourcePath>,
+    body: Json<SourceConfig>,
+    config: Data<SrvConfig>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+) -> Result<HttpResponse> {
+    check_admin_token(&req, config.admin_token.as_deref())?;
+    let id = sources
+        .add_source(&path.source_id, body.into_inner())
+        .await?;
+    cache.invalidate_all();
+    let config = SourceConfig::find(&sources.load(), &id)
+        .ok_or_else(|| ErrorNotFound(format!("Source {id} does not exist")))?;
+    Ok(HttpResponse::Created().json(AdminSource { id, config }))
+}
+
+/// Remove a source from the running server
+#[route("/_/sources/{source_id}", method = "DELETE")]
+async fn delete_admin_source(
+    req: HttpRequest,
+    path: Path<AdminSourcePath>,
+    config: Data<SrvConfig>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+) -> Result<HttpResponse> {
+    check_admin_token(&req, config.admin_token.as_deref())?;
+    if sources.remove_source(&path.source_id).await? {
+        cache.invalidate_all();
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ErrorNotFound(format!(
+            "Source {} does not exist",
+            path.source_id
+        )))
+    }
+}
+
+/// Administrative endpoints are only enabled if the admin token is configured,
+/// and require it to be passed as a bearer token in the `Authorization` header.
+fn check_admin_token(req: &HttpRequest, admin_token: Option<&str>) -> Result<()> {
+    let Some(admin_token) = admin_token else {
+        return Err(ErrorForbidden(
+            "Administrative endpoints are disabled, set admin_token to enable them",
+        ));
+    };
+    let is_valid = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        == Some(admin_token);
+    if is_valid {
+        Ok(())
+    } else {
+        Err(ErrorUnauthorized("Missing or invalid admin token"))
+    }
 }
 
 #[route("/sprite/{source_ids}.png", method = "GET", method = "HEAD")]
 async fn get_sprite_png(
     path: Path<TileJsonRequest>,
-    sprites: Data<SpriteSources>,
+    sources: Data<SharedSources>,
+    config: Data<SrvConfig>,
 ) -> Result<HttpResponse> {
+    let sprites = &sources.load().sprites;
+    let cache_control = sprites
+        .get_cache_control(&path.source_ids)
+        .map_err(map_sprite_error)?;
     let sheet = sprites
         .get_sprites(&path.source_ids)
         .await
         .map_err(map_sprite_error)?;
-    Ok(HttpResponse::Ok()
+    let mut response = HttpResponse::Ok();
+    insert_cache_control(&mut response, get_cache_control(&config, cache_control));
+    Ok(response
         .content_type(ContentType::png())
         .body(sheet.encode_png().map_err(map_internal_error)?))
 }
@@ -121,13 +436,63 @@ async fn get_sprite_png(
 )]
 async fn get_sprite_json(
     path: Path<TileJsonRequest>,
-    sprites: Data<SpriteSources>,
+    sources: Data<SharedSources>,
+    config: Data<SrvConfig>,
 ) -> Result<HttpResponse> {
+    let sprites = &sources.load().sprites;
+    let cache_control = sprites
+        .get_cache_control(&path.source_ids)
+        .map_err(map_sprite_error)?;
     let sheet = sprites
         .get_sprites(&path.source_ids)
         .await
         .map_err(map_sprite_error)?;
-    Ok(HttpResponse::Ok().json(sheet.get_index()))
+    let mut response = HttpResponse::Ok();
+    insert_cache_control(&mut response, get_cache_control(&config, cache_control));
+    Ok(response.json(sheet.get_index()))
+}
+
+#[route(
+    "/font/{fontstack}/{start:\\d+}-{end:\\d+}.pbf",
+    method = "GET",
+    method = "HEAD",
+    wrap = "middleware::Compress::default()"
+)]
+async fn get_font(path: Path<FontRequest>, sources: Data<SharedSources>) -> Result<HttpResponse> {
+    let sources = sources.load();
+    // Rendering the glyphs is CPU-intensive, so it runs on the blocking thread pool
+    let data = web::block(move || {
+        sources
+            .fonts
+            .get_font_range(&path.fontstack, path.start, path.end)
+    })
+    .await
+    .map_err(map_internal_error)?
+    .map_err(map_font_error)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-protobuf")
+        .body(data))
+}
+
+#[route(
+    "/style/{style_id}",
+    method = "GET",
+    method = "HEAD",
+    wrap = "middleware::Compress::default()"
+)]
+async fn get_style(
+    req: HttpRequest,
+    path: Path<StyleRequest>,
+    sources: Data<SharedSources>,
+    config: Data<SrvConfig>,
+) -> Result<HttpResponse> {
+    let sources = sources.load();
+    let style = sources
+        .styles
+        .get_style(&path.style_id, &sources, &get_base_url(&req, &config)?)
+        .await
+        .map_err(map_style_error)?;
+    Ok(HttpResponse::Ok().json(style))
 }
 
 #[route(
@@ -140,39 +505,126 @@ async fn get_sprite_json(
 async fn git_source_info(
     req: HttpRequest,
     path: Path<TileJsonRequest>,
-    sources: Data<Sources>,
+    query: Query<TileJsonQuery>,
+    sources: Data<SharedSources>,
+    config: Data<SrvConfig>,
+    scope: AuthScope,
 ) -> Result<HttpResponse> {
-    let sources = sources.get_sources(&path.source_ids, None)?.0;
+    scope.check_sources(&path.source_ids)?;
+    let sources = sources.load();
+    let sources = sources.sources.get_sources(&path.source_ids, None)?.0;
+    let cache_control = get_cache_control(&config, sources.iter().map(|s| s.get_cache_control()));
 
-    let tiles_path = req
+    let tiles_url = get_tiles_url(&req, &config, query.scheme)?;
+    let mut tilejson = merge_tilejson(sources, tiles_url);
+    if query.scheme == TileScheme::Tms {
+        tilejson.scheme = Some(query.scheme.tilejson_scheme().to_string());
+    }
+
+    let mut response = HttpResponse::Ok();
+    insert_cache_control(&mut response, cache_control);
+    Ok(response.json(tilejson))
+}
+
+/// The `Cache-Control` header value of a response built from the given sources' values,
+/// using the server-wide value for the sources without one. If the sources' values differ,
+/// the most restrictive one is used: `no-store`, then `no-cache`, then the shortest `max-age`.
+fn get_cache_control(
+    config: &SrvConfig,
+    values: impl IntoIterator<Item = Option<String>>,
+) -> Option<String> {
+    let default = cache_control_value(config.cache_control.as_deref(), config.max_age);
+    let mut values = values.into_iter().peekable();
+    if values.peek().is_none() {
+        return default;
+    }
+    most_restrictive_cache_control(values.map(|v| v.or_else(|| default.clone())))
+}
+
+fn insert_cache_control(response: &mut HttpResponseBuilder, cache_control: Option<String>) {
+    if let Some(value) = cache_control {
+        response.insert_header((CACHE_CONTROL, value));
+    }
+}
+
+/// The request path relative to the configured `base_path`
+fn get_relative_path<'a>(req: &'a HttpRequest, config: &SrvConfig) -> &'a str {
+    let path = req.path();
+    config
+        .base_path
+        .as_deref()
+        .and_then(|base_path| path.strip_prefix(base_path))
+        .unwrap_or(path)
+}
+
+/// The URL of this server as seen by the client. This is the `public_url` if configured,
+/// or e.g. `http://example.org/tiles` if the server is mounted under the `/tiles` base path,
+/// or is behind a reverse proxy which passes the original request path in the `x-rewrite-url` header.
+fn get_base_url(req: &HttpRequest, config: &SrvConfig) -> Result<String> {
+    if let Some(public_url) = &config.public_url {
+        return Ok(public_url.trim_end_matches('/').to_string());
+    }
+
+    let relative_path = get_relative_path(req, config);
+    let prefix = req
         .headers()
         .get("x-rewrite-url")
         .and_then(parse_x_rewrite_url)
-        .unwrap_or_else(|| req.path().to_owned());
+        .and_then(|path| path.strip_suffix(relative_path).map(ToString::to_string))
+        .or_else(|| config.base_path.clone())
+        .filter(|prefix| !prefix.is_empty())
+        .unwrap_or_else(|| "/".to_string());
 
     let info = req.connection_info();
-    let tiles_url = get_tiles_url(info.scheme(), info.host(), req.query_string(), &tiles_path)?;
-
-    Ok(HttpResponse::Ok().json(merge_tilejson(sources, tiles_url)))
+    Uri::builder()
+        .scheme(info.scheme())
+        .authority(info.host())
+        .path_and_query(prefix)
+        .build()
+        .map(|url| url.to_string().trim_end_matches('/').to_string())
+        .map_err(|e| ErrorBadRequest(format!("Can't build base URL: {e}")))
 }
 
-fn get_tiles_url(scheme: &str, host: &str, query_string: &str, tiles_path: &str) -> Result<String> {
-    let path_and_query = if query_string.is_empty() {
-        format!("{tiles_path}/{{z}}/{{x}}/{{y}}")
+/// The tiles URL template of a `TileJSON` request in the given scheme,
+/// keeping the request query except for the `scheme` parameter
+fn get_tiles_url(req: &HttpRequest, config: &SrvConfig, scheme: TileScheme) -> Result<String> {
+    let query_string = req
+        .query_string()
+        .split('&')
+        .filter(|v| !v.is_empty() && *v != "scheme" && !v.starts_with("scheme="))
+        .collect::<Vec<_>>()
+        .join("&");
+    let query = if query_string.is_empty() {
+        String::new()
     } else {
-        format!("{tiles_path}/{{z}}/{{x}}/{{y}}?{query_string}")
+        format!("?{query_string}")
     };
+    let template = scheme.tiles_path();
+
+    if let Some(public_url) = &config.public_url {
+        let public_url = public_url.trim_end_matches('/');
+        let tiles_path = get_relative_path(req, config);
+        return Ok(format!("{public_url}{tiles_path}{template}{query}"));
+    }
 
+    let tiles_path = req
+        .headers()
+        .get("x-rewrite-url")
+        .and_then(parse_x_rewrite_url)
+        .unwrap_or_else(|| req.path().to_owned());
+
+    let info = req.connection_info();
     Uri::builder()
-        .scheme(scheme)
-        .authority(host)
-        .path_and_query(path_and_query)
+        .scheme(info.scheme())
+        .authority(info.host())
+        .path_and_query(format!("{tiles_path}{template}{query}"))
         .build()
         .map(|tiles_url| tiles_url.to_string())
         .map_err(|e| ErrorBadRequest(format!("Can't build tiles URL: {e}")))
 }
 
-fn merge_tilejson(sources: Vec<&dyn Source>, tiles_url: String) -> TileJSON {
+#[must_use]
+pub fn merge_tilejson(sources: Vec<&dyn Source>, tiles_url: String) -> TileJSON {
     if sources.len() == 1 {
         let mut tj = sources[0].get_tilejson();
         tj.tiles = vec![tiles_url];
@@ -264,44 +716,202 @@ fn merge_tilejson(sources: Vec<&dyn Source>, tiles_url: String) -> TileJSON {
     result
 }
 
-#[route("/{source_ids}/{z}/{x}/{y}", method = "GET", method = "HEAD")]
+#[route(
+    "/{source_ids}/{z}/{x}/{y}",
+    method = "GET",
+    method = "HEAD",
+    wrap = "RateLimiting"
+)]
 async fn get_tile(
     req: HttpRequest,
-    path: Path<TileRequest>,
-    sources: Data<Sources>,
+    path: Path<TilePath>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+    metrics: Data<Metrics>,
+    config: Data<SrvConfig>,
+    scope: AuthScope,
 ) -> Result<HttpResponse> {
-    let xyz = Xyz {
-        z: path.z,
-        x: path.x,
-        y: path.y,
+    let tile = TileRequest::from_xyz(path.into_inner())?;
+    serve_tile(req, tile, sources, cache, metrics, config, scope).await
+}
+
+#[route(
+    "/{source_ids}/tms/{z}/{x}/{y}",
+    method = "GET",
+    method = "HEAD",
+    wrap = "RateLimiting"
+)]
+async fn get_tms_tile(
+    req: HttpRequest,
+    path: Path<TilePath>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+    metrics: Data<Metrics>,
+    config: Data<SrvConfig>,
+    scope: AuthScope,
+) -> Result<HttpResponse> {
+    let tile = TileRequest::from_tms(path.into_inner())?;
+    serve_tile(req, tile, sources, cache, metrics, config, scope).await
+}
+
+#[route(
+    "/{source_ids}/q/{quadkey}",
+    method = "GET",
+    method = "HEAD",
+    wrap = "RateLimiting"
+)]
+async fn get_quadkey_tile(
+    req: HttpRequest,
+    path: Path<QuadkeyPath>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+    metrics: Data<Metrics>,
+    config: Data<SrvConfig>,
+    scope: AuthScope,
+) -> Result<HttpResponse> {
+    let tile = TileRequest::from_quadkey(path.into_inner())?;
+    serve_tile(req, tile, sources, cache, metrics, config, scope).await
+}
+
+/// Get many tiles of a source in one response, streaming them in the order of the request.
+/// See [`encode_batch_tile`] for the response format. Each tile counts towards the rate limit.
+#[route("/{source_ids}/batch", method = "POST")]
+async fn post_tile_batch(
+    req: HttpRequest,
+    path: Path<TileJsonRequest>,
+    body: Json<BatchRequest>,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+    scope: AuthScope,
+) -> Result<HttpResponse> {
+    scope.check_sources(&path.source_ids)?;
+    let tiles = body.get_tiles()?;
+    for xyz in &tiles {
+        scope.check_tile(*xyz)?;
+    }
+    check_tile_count(&req, tiles.len())?;
+    let src: Arc<dyn Source> = sources
+        .load()
+        .sources
+        .get_source(&path.source_ids)?
+        .clone_source()
+        .into();
+    let query = if src.support_url_query() {
+        Some(get_url_query(&req)?)
+    } else {
+        None
     };
+    let info = src.get_tile_info();
+    let cache = TileCache::clone(&cache);
+
+    // Tiles are fetched concurrently, but with a limit to avoid exhausting the connection pool
+    let tiles = stream::iter(tiles)
+        .map(move |xyz| {
+            let (src, cache, query) = (src.clone(), cache.clone(), query.clone());
+            async move {
+                let tile = if src.is_valid_zoom(xyz.z) {
+                    let key = CacheKey::new(src.get_id(), xyz, &query);
+                    cache
+                        .get_or_insert(key, src.get_tile(&xyz, &query))
+                        .await
+                        .map_err(map_internal_error)?
+                } else {
+                    TileData::default()
+                };
+                Ok::<_, actix_web::Error>(Bytes::from(encode_batch_tile(
+                    xyz,
+                    info.encoding,
+                    &tile.data,
+                )))
+            }
+        })
+        .buffered(BATCH_CONCURRENCY);
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .insert_header(("x-tile-content-type", info.format.content_type()))
+        .content_type("application/octet-stream")
+        .streaming(tiles))
+}
+
+async fn serve_tile(
+    req: HttpRequest,
+    path: TileRequest,
+    sources: Data<SharedSources>,
+    cache: Data<TileCache>,
+    metrics: Data<Metrics>,
+    config: Data<SrvConfig>,
+    scope: AuthScope,
+) -> Result<HttpResponse> {
+    let start = Instant::now();
+    let sources = sources.load();
+    // Denied requests are also recorded in the metrics, but all of them with the same label,
+    // together with the requests of unknown sources, so that the number of labels is bounded
+    let access = check_tile_access(&scope, &path);
+    let is_known = |id| sources.sources.get_source(id).is_ok();
+    let label = if access.is_ok() && path.source_ids.split(',').all(is_known) {
+        Metrics::source_label(&path.source_ids)
+    } else {
+        UNKNOWN_SOURCE
+    };
+    let result = match access {
+        Ok(()) => {
+            let sources = &sources.sources;
+            get_tile_response(&req, &path, label, sources, &cache, &metrics, &config).await
+        }
+        Err(e) => Err(e),
+    };
+    let (status, size) = match &result {
+        Ok(response) => match response.body().size() {
+            BodySize::Sized(size) if response.status() == StatusCode::OK => {
+                (response.status(), usize::try_from(size).ok())
+            }
+            _ => (response.status(), None),
+        },
+        Err(e) => (e.as_response_error().status_code(), None),
+    };
+    metrics.observe_tile(label, path.xyz.z, status, start.elapsed(), size);
+    result
+}
+
+fn check_tile_access(scope: &AuthScope, path: &TileRequest) -> Result<()> {
+    scope.check_sources(&path.source_ids)?;
+    scope.check_tile(path.xyz)
+}
+
+async fn get_tile_response(
+    req: &HttpRequest,
+    path: &TileRequest,
+    label: &str,
+    sources: &Sources,
+    cache: &TileCache,
+    metrics: &Metrics,
+    config: &SrvConfig,
+) -> Result<HttpResponse> {
+    let xyz = path.xyz;
+    let layers = Query::<LayersQuery>::from_query(req.query_string())?.into_inner();
+    let layers = LayerFilter::new(layers.layers.as_deref(), layers.exclude_layers.as_deref());
 
     // Optimization for a single-source request.
-    let (tile, info) = if path.source_ids.contains(',') {
-        let (sources, use_url_query, info) = sources.get_sources(&path.source_ids, Some(path.z))?;
+    let (tile, info, query, cache_control, vary_accept) = if path.source_ids.contains(',') {
+        let (mut sources, use_url_query, info) =
+            sources.get_sources(&path.source_ids, Some(xyz.z))?;
         if sources.is_empty() {
             return Err(ErrorNotFound("No valid sources found"));
         }
+        let cache_control =
+            get_cache_control(config, sources.iter().map(|s| s.get_cache_control()));
+        // Like in single-source requests, the sources without the requested layers have no tiles
+        sources.retain(|src| Sources::check_layers(*src, src.get_id(), layers.as_ref()));
         let query = if use_url_query {
-            Some(Query::<UrlQuery>::from_query(req.query_string())?.into_inner())
+            Some(get_url_query(req)?)
         } else {
             None
         };
-        let tiles = try_join_all(sources.into_iter().map(|s| s.get_tile(&xyz, &query)))
-            .await
-            .map_err(map_internal_error)?;
-        // Make sure tiles can be concatenated, or if not, that there is only one non-empty tile for each zoom level
-        // TODO: can zlib, brotli, or zstd be concatenated?
-        // TODO: implement decompression step for other concatenate-able formats
-        let can_join = info.format == Format::Mvt
-            && (info.encoding == Encoding::Uncompressed || info.encoding == Encoding::Gzip);
-        if !can_join && tiles.iter().filter(|v| !v.is_empty()).count() > 1 {
-            return Err(ErrorBadRequest(format!(
-                "Can't merge {info} tiles. Make sure there is only one non-empty tile source at zoom level {}",
-                xyz.z
-            )))?;
-        }
-        (tiles.concat(), info)
+        // The format of the blended raster tiles depends on the `Accept` header
+        let vary_accept = is_blendable(info.format);
+        let (tile, info, query) = get_composite_tile(req, sources, info, xyz, query, cache).await?;
+        (tile, info, query, cache_control, vary_accept)
     } else {
         let id = &path.source_ids;
         let zoom = xyz.z;
@@ -312,129 +922,498 @@ async fn get_tile(
             )));
         }
         let query = if src.support_url_query() {
-            Some(Query::<UrlQuery>::from_query(req.query_string())?.into_inner())
+            Some(get_url_query(req)?)
         } else {
             None
         };
-        let tile = src
-            .get_tile(&xyz, &query)
-            .await
-            .map_err(map_internal_error)?;
-        (tile, src.get_tile_info())
+        let tile = if Sources::check_layers(src, id, layers.as_ref()) {
+            cache
+                .get_or_insert(CacheKey::new(id, xyz, &query), src.get_tile(&xyz, &query))
+                .await
+                .map_err(map_internal_error)?
+        } else {
+            TileData::default()
+        };
+        let cache_control = get_cache_control(config, [src.get_cache_control()]);
+        (tile, src.get_tile_info(), query, cache_control, false)
+    };
+
+    let (tile, info) = match &layers {
+        Some(layers) if info.format == Format::Mvt && !tile.is_empty() => {
+            filter_tile_layers(tile, info, layers).map_err(map_internal_error)?
+        }
+        _ => (tile, info),
     };
 
     Ok(if tile.is_empty() {
-        HttpResponse::NoContent().finish()
+        let mut response = HttpResponse::NoContent();
+        insert_cache_control(&mut response, cache_control);
+        response.finish()
     } else {
-        // decide if (re-)encoding of the tile data is needed, and recompress if so
-        let (tile, info) = recompress(tile, info, req.get_header::<AcceptEncoding>())?;
-        let mut response = HttpResponse::Ok();
+        // The same tile may be sent with different content encodings,
+        // so it is only semantically equivalent to the stored one, i.e. weak
+        let etag = tile.etag.as_deref().and_then(to_weak_etag);
+        let last_modified = tile.last_modified.map(HttpDate::from);
+        let not_modified = is_not_modified(req, etag.as_ref(), last_modified);
+        let mut response = if not_modified {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        insert_cache_control(&mut response, cache_control);
+        if vary_accept {
+            response.insert_header((VARY, "Accept"));
+        }
+        insert_validators(&mut response, etag, last_modified);
+        if not_modified {
+            return Ok(response.finish());
+        }
+
+        // decide if (re-)encoding of the tile data is needed, and recompress if so,
+        // keeping the re-compressed tile in the cache for the next requests
+        let encoding = negotiate_encoding(info, req.get_header::<AcceptEncoding>())?;
+        let data = if encoding == info.encoding {
+            tile.data
+        } else {
+            let key = CacheKey::new(&path.source_ids, xyz, &query)
+                .encoding(encoding)
+                .layers(layers);
+            let tile = async {
+                metrics.observe_recompression(label, info.encoding, encoding);
+                recompress(tile.data, info, encoding).map(TileData::new)
+            };
+            cache
+                .get_or_insert(key, tile)
+                .await
+                .map_err(map_internal_error)?
+                .data
+        };
         response.content_type(info.format.content_type());
-        if let Some(val) = info.encoding.content_encoding() {
+        if let Some(val) = encoding.content_encoding() {
             response.insert_header((CONTENT_ENCODING, val));
         }
-        response.body(tile)
+        response.body(data)
     })
 }
 
-fn recompress(
-    mut tile: Vec<u8>,
-    mut info: TileInfo,
-    accept_enc: Option<AcceptEncoding>,
-) -> Result<(Vec<u8>, TileInfo)> {
+fn insert_validators(
+    response: &mut HttpResponseBuilder,
+    etag: Option<EntityTag>,
+    last_modified: Option<HttpDate>,
+) {
+    if let Some(etag) = etag {
+        response.insert_header(ETag(etag));
+    }
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+}
+
+/// The query parameters passed to the sources. If the authentication is configured,
+/// the API key is removed, so that it does not reach the functions, nor the cache keys.
+fn get_url_query(req: &HttpRequest) -> Result<UrlQuery> {
+    let mut query = Query::<UrlQuery>::from_query(req.query_string())?.into_inner();
+    if is_auth_enabled(req) {
+        query.remove("key");
+    }
+    Ok(query)
+}
+
+/// Get the tiles of all sources of a composite source, and merge them into one
+async fn get_composite_tile(
+    req: &HttpRequest,
+    sources: Vec<&dyn Source>,
+    info: TileInfo,
+    xyz: Xyz,
+    query: Option<UrlQuery>,
+    cache: &TileCache,
+) -> Result<(TileData, TileInfo, Option<UrlQuery>)> {
+    if sources.is_empty() {
+        return Ok((TileData::default(), info, query));
+    }
+    let infos: Vec<_> = sources.iter().map(|s| s.get_tile_info()).collect();
+    let tiles = try_join_all(sources.into_iter().map(|s| {
+        let key = CacheKey::new(s.get_id(), xyz, &query);
+        cache.get_or_insert(key, s.get_tile(&xyz, &query))
+    }))
+    .await
+    .map_err(map_internal_error)?;
+    let info = if is_blendable(info.format) {
+        let format = negotiate_raster_format(info.format, req.get_header::<Accept>());
+        TileInfo::new(format, info.encoding)
+    } else {
+        info
+    };
+    let (tile, info) = merge_composite_tiles(tiles, &infos, info, xyz.z).await?;
+    Ok((tile, info, query))
+}
+
+/// Remove the layers that were not requested from a vector tile, decoding it if needed.
+/// The filtered tile is uncompressed, and its validators are specific to the requested layers.
+fn filter_tile_layers(
+    tile: TileData,
+    info: TileInfo,
+    layers: &LayerFilter,
+) -> io::Result<(TileData, TileInfo)> {
+    let data = recompress(tile.data, info, Encoding::Uncompressed)?;
+    let etag = tile
+        .etag
+        .map(|etag| format!("{etag}-{:x}", layers.stable_hash()));
+    let tile = TileData::new(layers.filter_tile(&data)?)
+        .etag(etag)
+        .last_modified(tile.last_modified);
+    Ok((tile, info.encoding(Encoding::Uncompressed)))
+}
+
+async fn merge_composite_tiles(
+    tiles: Vec<TileData>,
+    infos: &[TileInfo],
+    info: TileInfo,
+    zoom: u8,
+) -> Result<(TileData, TileInfo)> {
+    if info.format == Format::Mvt {
+        // Vector tiles are merged by concatenating their layers, decoding them if needed
+        let tiles = tiles
+            .into_iter()
+            .zip(infos.iter().map(|v| v.encoding))
+            .collect();
+        let (tile, encoding) = merge_vector_tiles(tiles).map_err(map_internal_error)?;
+        Ok((tile, info.encoding(encoding)))
+    } else if is_blendable(info.format) {
+        // Raster tiles are blended unless there is a single tile already in the requested format
+        let mut parts = tiles.iter().zip(infos).filter(|(tile, _)| !tile.is_empty());
+        let single = match (parts.next(), parts.next()) {
+            (Some((_, inf)), None) => inf.format == info.format,
+            _ => false,
+        };
+        if single {
+            Ok((merge_tiles(&tiles), info))
+        } else {
+            let tiles = tiles
+                .into_iter()
+                .zip(infos.iter().map(|v| v.format))
+                .collect();
+            // Decoding and encoding the images is CPU-intensive, so it runs on the blocking thread pool
+            let format = info.format;
+            let tile = web::block(move || blend_raster_tiles(tiles, format))
+                .await
+                .map_err(map_internal_error)?
+                .map_err(map_internal_error)?;
+            Ok((tile, info))
+        }
+    } else if tiles.iter().filter(|v| !v.is_empty()).count() > 1 {
+        // Other formats cannot be merged, so there must be only one non-empty tile for each zoom level
+        Err(ErrorBadRequest(format!(
+            "Can't merge {info} tiles. Make sure there is only one non-empty tile source at zoom level {zoom}"
+        )))
+    } else {
+        Ok((merge_tiles(&tiles), info))
+    }
+}
+
+/// Merge the vector tiles of a composite source, returning the merged tile with its encoding.
+/// The tiles are concatenated as is if they are all uncompressed or all gzip-compressed,
+/// otherwise they are decoded first, and the merged tile is uncompressed.
+fn merge_vector_tiles(tiles: Vec<(TileData, Encoding)>) -> io::Result<(TileData, Encoding)> {
+    let mut encodings = tiles
+        .iter()
+        .filter(|(tile, _)| !tile.is_empty())
+        .map(|(_, encoding)| *encoding);
+    let encoding = match encodings.next() {
+        Some(first) if encodings.all(|v| v == first) => first,
+        _ => Encoding::Uncompressed,
+    };
+    let encoding = match encoding {
+        Encoding::Gzip => Encoding::Gzip,
+        _ => Encoding::Uncompressed,
+    };
+
+    let tiles = tiles
+        .into_iter()
+        .map(|(tile, tile_encoding)| {
+            if tile.is_empty() || tile_encoding == encoding {
+                return Ok(tile);
+            }
+            let info = TileInfo::new(Format::Mvt, tile_encoding);
+            Ok(TileData {
+                data: recompress(tile.data, info, encoding)?,
+                ..tile
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok((merge_tiles(&tiles), encoding))
+}
+
+/// Choose the format of a blended raster tile. This is the first source's format,
+/// unless the client prefers another encodable format in its `Accept` header.
+/// `WebP` cannot be encoded, so such tiles are blended into PNG instead.
+fn negotiate_raster_format(format: Format, accept: Option<Accept>) -> Format {
+    let default = match format {
+        Format::Png | Format::Jpeg => format,
+        _ => Format::Png,
+    };
+    for mime in accept.map(|v| v.ranked()).unwrap_or_default() {
+        match (mime.type_().as_str(), mime.subtype().as_str()) {
+            ("*", _) | ("image", "*") => return default,
+            ("image", "png") => return Format::Png,
+            ("image", "jpeg") => return Format::Jpeg,
+            _ => {}
+        }
+    }
+    default
+}
+
+/// Decode the raster tiles of a composite source, and alpha-composite them in the order
+/// of the sources, encoding the result in the given format. All tiles are scaled to the
+/// size of the first non-empty one.
+fn blend_raster_tiles(
+    tiles: Vec<(TileData, Format)>,
+    format: Format,
+) -> image::ImageResult<TileData> {
+    let mut blended: Option<RgbaImage> = None;
+    for (tile, tile_format) in &tiles {
+        if tile.is_empty() {
+            continue;
+        }
+        let image_format =
+            ImageFormat::from_mime_type(tile_format.content_type()).unwrap_or(ImageFormat::Png);
+        let image = image::load_from_memory_with_format(&tile.data, image_format)?.into_rgba8();
+        if let Some(base) = &mut blended {
+            let image = if image.dimensions() == base.dimensions() {
+                image
+            } else {
+                imageops::resize(&image, base.width(), base.height(), FilterType::Triangle)
+            };
+            imageops::overlay(base, &image, 0, 0);
+        } else {
+            blended = Some(image);
+        }
+    }
+    let Some(blended) = blended else {
+        return Ok(TileData::default());
+    };
+
+    let mut data = Cursor::new(Vec::new());
+    if format == Format::Jpeg {
+        // JPEG has no alpha channel
+        let image = DynamicImage::ImageRgba8(blended).into_rgb8();
+        image.write_to(&mut data, ImageOutputFormat::Jpeg(90))?;
+    } else {
+        blended.write_to(&mut data, ImageOutputFormat::Png)?;
+    }
+    let parts: Vec<_> = tiles.into_iter().map(|(tile, _)| tile).collect();
+    let (etag, last_modified) = merge_validators(&parts);
+    // The same tiles may be blended into different formats
+    let etag = etag.map(|v| format!("{v}-{}", format.content_type()));
+    Ok(TileData::new(data.into_inner())
+        .etag(etag)
+        .last_modified(last_modified))
+}
+
+/// Concatenate composite tiles. The result has validators only if every part has them.
+#[must_use]
+pub fn merge_tiles(tiles: &[TileData]) -> TileData {
+    let (etag, last_modified) = merge_validators(tiles);
+    let data = if let [tile] = tiles {
+        tile.data.clone()
+    } else {
+        tiles
+            .iter()
+            .map(|t| &t.data[..])
+            .collect::<Vec<_>>()
+            .concat()
+            .into()
+    };
+    TileData::new(data).etag(etag).last_modified(last_modified)
+}
+
+/// Combine the validators of the composite tile parts, if every part has them
+fn merge_validators(tiles: &[TileData]) -> (Option<String>, Option<SystemTime>) {
+    let etag = tiles
+        .iter()
+        .map(|t| t.etag.as_deref())
+        .collect::<Option<Vec<_>>>()
+        .map(|v| v.join("-"));
+    let last_modified = tiles
+        .iter()
+        .map(|t| t.last_modified)
+        .collect::<Option<Vec<_>>>()
+        .and_then(|v| v.into_iter().max());
+    (etag, last_modified)
+}
+
+/// Convert a source-provided tile version into a weak entity tag, if it only has valid characters
+fn to_weak_etag(tag: &str) -> Option<EntityTag> {
+    let is_valid = |c: u8| c == 0x21 || (0x23..=0x7e).contains(&c) || c >= 0x80;
+    if !tag.is_empty() && tag.bytes().all(is_valid) {
+        Some(EntityTag::new_weak(tag.to_string()))
+    } else {
+        debug!("Ignoring tile etag {tag:?} because it contains invalid characters");
+        None
+    }
+}
+
+/// Evaluate `If-None-Match` and `If-Modified-Since` request headers as per RFC 7232.
+/// `If-Modified-Since` is ignored if `If-None-Match` is present.
+fn is_not_modified(
+    req: &HttpRequest,
+    etag: Option<&EntityTag>,
+    last_modified: Option<HttpDate>,
+) -> bool {
+    if let Some(if_none_match) = req.get_header::<IfNoneMatch>() {
+        match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(items) => {
+                etag.map_or(false, |etag| items.iter().any(|v| v.weak_eq(etag)))
+            }
+        }
+    } else if let (Some(IfModifiedSince(since)), Some(modified)) =
+        (req.get_header::<IfModifiedSince>(), last_modified)
+    {
+        modified <= since
+    } else {
+        false
+    }
+}
+
+/// Decide which encoding the tile should be sent with, based on the `Accept-Encoding` request header
+fn negotiate_encoding(info: TileInfo, accept_enc: Option<AcceptEncoding>) -> Result<Encoding> {
+    let mut encoding = info.encoding;
     if let Some(accept_enc) = accept_enc {
-        if info.encoding.is_encoded() {
+        if encoding.is_encoded() {
             // already compressed, see if we can send it as is, or need to re-compress
             if !accept_enc.iter().any(|e| {
                 if let Preference::Specific(HeaderEnc::Known(enc)) = e.item {
-                    to_encoding(enc) == Some(info.encoding)
+                    to_encoding(enc) == Some(encoding)
                 } else {
                     false
                 }
             }) {
                 // need to re-compress the tile - uncompress it first
-                (tile, info) = decode(tile, info)?;
+                encoding = decoded_encoding(info)?;
             }
         }
-        if info.encoding == Encoding::Uncompressed {
+        if encoding == Encoding::Uncompressed {
             // only apply compression if the content supports it
             if let Some(HeaderEnc::Known(enc)) = accept_enc.negotiate(SUPPORTED_ENCODINGS.iter()) {
                 // (re-)compress the tile into the preferred encoding
-                (tile, info) = encode(tile, info, enc)?;
+                encoding = to_encoding(enc).unwrap_or(encoding);
             }
         }
-        Ok((tile, info))
+        Ok(encoding)
     } else {
         // no accepted-encoding header, decode the tile if compressed
-        decode(tile, info)
+        decoded_encoding(info)
     }
 }
 
-fn encode(tile: Vec<u8>, info: TileInfo, enc: ContentEncoding) -> Result<(Vec<u8>, TileInfo)> {
-    Ok(match enc {
-        ContentEncoding::Brotli => (encode_brotli(&tile)?, info.encoding(Encoding::Brotli)),
-        ContentEncoding::Gzip => (encode_gzip(&tile)?, info.encoding(Encoding::Gzip)),
-        _ => (tile, info),
-    })
-}
-
-fn decode(tile: Vec<u8>, info: TileInfo) -> Result<(Vec<u8>, TileInfo)> {
-    Ok(if info.encoding.is_encoded() {
-        match info.encoding {
-            Encoding::Gzip => (decode_gzip(&tile)?, info.encoding(Encoding::Uncompressed)),
-            Encoding::Brotli => (decode_brotli(&tile)?, info.encoding(Encoding::Uncompressed)),
-            _ => Err(ErrorBadRequest(format!(
-                "Tile is is stored as {info}, but the client does not accept this encoding"
-            )))?,
+/// Encoding of the tile after decoding it, or an error if the tile encoding is not supported
+fn decoded_encoding(info: TileInfo) -> Result<Encoding> {
+    match info.encoding {
+        Encoding::Gzip | Encoding::Zlib | Encoding::Brotli | Encoding::Zstd => {
+            Ok(Encoding::Uncompressed)
         }
-    } else {
-        (tile, info)
-    })
+        v if v.is_encoded() => Err(ErrorBadRequest(format!(
+            "Tile is is stored as {info}, but the client does not accept this encoding"
+        ))),
+        v => Ok(v),
+    }
 }
 
+/// Convert tile data from its stored encoding to the one chosen by [`negotiate_encoding`]
+/// The tile is returned as is, without copying, if no conversion is needed.
 fn to_encoding(val: ContentEncoding) -> Option<Encoding> {
     Some(match val {
         ContentEncoding::Identity => Encoding::Uncompressed,
         ContentEncoding::Gzip => Encoding::Gzip,
+        ContentEncoding::Deflate => Encoding::Zlib,
         ContentEncoding::Brotli => Encoding::Brotli,
-        // TODO: Deflate => Encoding::Zstd or Encoding::Zlib ?
+        ContentEncoding::Zstd => Encoding::Zstd,
         _ => None?,
     })
 }
 
 pub fn router(cfg: &mut web::ServiceConfig) {
     cfg.service(get_health)
+        .service(get_status)
         .service(get_index)
         .service(get_catalog)
+        .service(get_metrics)
+        .service(post_reload)
+        .service(get_admin_source)
+        .service(post_admin_source)
+        .service(delete_admin_source)
+        .service(get_font)
+        .service(get_style)
         .service(git_source_info)
         .service(get_tile)
+        .service(get_tms_tile)
+        .service(get_quadkey_tile)
+        .service(post_tile_batch)
         .service(get_sprite_json)
         .service(get_sprite_png);
 }
 
 /// Create a new initialized Actix `App` instance together with the listening address.
-pub fn new_server(config: SrvConfig, all_sources: AllSources) -> crate::Result<(Server, String)> {
+/// If the sources have a loader, they are reloaded on `POST /reload` requests and on SIGHUP.
+/// If `tls_cert` and `tls_key` are configured, the server uses HTTPS, and re-reads them on SIGHUP.
+pub fn new_server(
+    mut config: SrvConfig,
+    sources: SharedSources,
+) -> crate::Result<(Server, String)> {
     let keep_alive = Duration::from_secs(config.keep_alive.unwrap_or(KEEP_ALIVE_DEFAULT));
     let worker_processes = config.worker_processes.unwrap_or_else(num_cpus::get);
     let listen_addresses = config
         .listen_addresses
+        .clone()
         .unwrap_or_else(|| LISTEN_ADDRESSES_DEFAULT.to_owned());
+    let tls =
+        TlsCertResolver::new(config.tls_cert.as_ref(), config.tls_key.as_ref())?.map(Arc::new);
+    let auth = Data::new(Auth::new(config.auth.as_ref())?);
+    let cors = CorsConfig::resolve(config.cors.as_ref())?;
+    let rate_limiter = Data::new(RateLimiter::new(config.rate_limit.as_ref()));
+    let cache = TileCache::new(config.cache.as_ref());
+    let metrics = Metrics::new();
+    let start_time = Data::new(StartTime(Instant::now()));
+    let sources = Data::new(sources);
+    config.base_path = normalize_base_path(config.base_path.as_deref());
+    let base_path = config.base_path.clone().unwrap_or_default();
+    let config = Data::new(config);
+
+    #[cfg(unix)]
+    if sources.can_reload() || tls.is_some() {
+        actix_rt::spawn(reload_on_sighup(
+            sources.clone(),
+            cache.clone(),
+            tls.clone(),
+        ));
+    }
 
     let server = HttpServer::new(move || {
-        let cors_middleware = Cors::default()
-            .allow_any_origin()
-            .allowed_methods(vec!["GET"])
-            .allowed_headers(vec![AUTHORIZATION, ACCEPT]);
+        // Condition requires a middleware even if it is disabled
+        let cors_middleware = cors
+            .as_ref()
+            .map_or_else(CorsConfig::default, Clone::clone)
+            .make_cors();
 
         App::new()
-            .app_data(Data::new(all_sources.sources.clone()))
-            .app_data(Data::new(all_sources.sprites.clone()))
-            .wrap(cors_middleware)
+            .app_data(config.clone())
+            .app_data(sources.clone())
+            .app_data(Data::new(cache.clone()))
+            .app_data(Data::new(metrics.clone()))
+            .app_data(auth.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(start_time.clone())
+            .wrap(middleware::Condition::new(cors.is_some(), cors_middleware))
             .wrap(middleware::NormalizePath::new(TrailingSlash::MergeOnly))
             .wrap(middleware::Logger::default())
-            .configure(router)
-    })
-    .bind(listen_addresses.clone())
+            .service(web::scope(&base_path).configure(router))
+    });
+
+    let server = match tls {
+        Some(tls) => server.bind_rustls_021(listen_addresses.clone(), tls.server_config()),
+        None => server.bind(listen_addresses.clone()),
+    }
     .map_err(|e| BindingError(e, listen_addresses.clone()))?
     .keep_alive(keep_alive)
     .shutdown_timeout(0)
@@ -444,6 +1423,45 @@ pub fn new_server(config: SrvConfig, all_sources: AllSources) -> crate::Result<(
     Ok((server, listen_addresses))
 }
 
+/// Make sure the base path starts with a slash and does not end with one, e.g. `/tiles`.
+/// Returns `None` if all routes are served from the root.
+fn normalize_base_path(base_path: Option<&str>) -> Option<String> {
+    base_path
+        .map(|v| v.trim_matches('/'))
+        .filter(|v| !v.is_empty())
+        .map(|v| format!("/{v}"))
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(
+    sources: Data<SharedSources>,
+    cache: TileCache,
+    tls: Option<Arc<TlsCertResolver>>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to listen for SIGHUP, sources will not be reloaded on it: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP");
+        if let Some(tls) = &tls {
+            if let Err(e) = tls.reload() {
+                error!("Unable to reload TLS certificate, keeping the current one: {e}");
+            }
+        }
+        match sources.reload().await {
+            Some(Ok(_)) => cache.invalidate_all(),
+            Some(Err(e)) => error!("Unable to reload sources: {e}"),
+            None => {}
+        }
+    }
+}
+
 fn parse_x_rewrite_url(header: &HeaderValue) -> Option<String> {
     header
         .to_str()
@@ -456,24 +1474,36 @@ fn parse_x_rewrite_url(header: &HeaderValue) -> Option<String> {
 mod tests {
     use std::collections::HashMap;
 
+    use actix_web::http::header::IF_NONE_MATCH;
     use async_trait::async_trait;
     use tilejson::{tilejson, Bounds, VectorLayer};
 
     use super::*;
-    use crate::source::{Source, Tile};
+    use crate::source::{Source, Tile, TileData};
+    use crate::test_utils::some;
     use crate::utils;
+    use crate::utils::{decode_gzip, encode_brotli, encode_gzip};
 
     #[derive(Debug, Clone)]
     struct TestSource {
+        id: &'static str,
         tj: TileJSON,
     }
 
     #[async_trait]
     impl Source for TestSource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
         fn get_tilejson(&self) -> TileJSON {
             self.tj.clone()
         }
 
+        fn get_kind(&self) -> SourceKind {
+            SourceKind::Mbtiles
+        }
+
         fn get_tile_info(&self) -> TileInfo {
             unimplemented!()
         }
@@ -494,15 +1524,434 @@ mod tests {
             &self,
             _xyz: &Xyz,
             _url_query: &Option<UrlQuery>,
-        ) -> Result<Tile, utils::Error> {
+        ) -> Result<TileData, utils::Error> {
             unimplemented!()
         }
     }
 
+    #[test]
+    fn test_not_modified() {
+        use std::time::{Duration, SystemTime};
+
+        use actix_web::test::TestRequest;
+
+        let etag = to_weak_etag("abc");
+        let modified = HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let earlier = HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(500_000));
+        let check = |req: TestRequest| {
+            is_not_modified(&req.to_http_request(), etag.as_ref(), Some(modified))
+        };
+
+        assert!(!check(TestRequest::default()));
+        assert!(check(
+            TestRequest::default().insert_header((IF_NONE_MATCH, r#""abc""#))
+        ));
+        assert!(check(
+            TestRequest::default().insert_header((IF_NONE_MATCH, r#"W/"abc""#))
+        ));
+        assert!(check(
+            TestRequest::default().insert_header((IF_NONE_MATCH, "*"))
+        ));
+        assert!(!check(
+            TestRequest::default().insert_header((IF_NONE_MATCH, r#""xyz""#))
+        ));
+        assert!(check(
+            TestRequest::default().insert_header(IfModifiedSince(modified))
+        ));
+        assert!(!check(
+            TestRequest::default().insert_header(IfModifiedSince(earlier))
+        ));
+        // If-None-Match takes precedence over If-Modified-Since
+        assert!(!check(
+            TestRequest::default()
+                .insert_header((IF_NONE_MATCH, r#""xyz""#))
+                .insert_header(IfModifiedSince(modified))
+        ));
+
+        assert_eq!(to_weak_etag(r#"a"b"#), None);
+        assert_eq!(to_weak_etag(""), None);
+    }
+
+    #[test]
+    fn test_admin_token() {
+        use actix_web::test::TestRequest;
+
+        let check = |req: TestRequest, token: Option<&str>| {
+            check_admin_token(&req.to_http_request(), token)
+                .map_err(|e| e.as_response_error().status_code())
+        };
+        let bearer = |v: &str| TestRequest::default().insert_header((AUTHORIZATION, v.to_string()));
+
+        assert_eq!(
+            check(bearer("Bearer abc"), None),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(check(bearer("Bearer abc"), Some("abc")), Ok(()));
+        assert_eq!(
+            check(bearer("Bearer xyz"), Some("abc")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            check(bearer("abc"), Some("abc")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            check(TestRequest::default(), Some("abc")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn test_base_url() {
+        use actix_web::test::TestRequest;
+
+        let config = SrvConfig::default();
+        let req = TestRequest::with_uri("/style/basic").insert_header(("host", "example.org"));
+        assert_eq!(
+            get_base_url(&req.to_http_request(), &config).unwrap(),
+            "http://example.org"
+        );
+
+        let req = TestRequest::with_uri("/style/basic")
+            .insert_header(("host", "example.org"))
+            .insert_header(("x-rewrite-url", "/tiles/style/basic"));
+        assert_eq!(
+            get_base_url(&req.to_http_request(), &config).unwrap(),
+            "http://example.org/tiles"
+        );
+
+        // The rewritten path does not end with the request path, so it cannot be used as a prefix
+        let req = TestRequest::with_uri("/style/basic")
+            .insert_header(("host", "example.org"))
+            .insert_header(("x-rewrite-url", "/tiles/my_style"));
+        assert_eq!(
+            get_base_url(&req.to_http_request(), &config).unwrap(),
+            "http://example.org"
+        );
+
+        let config = SrvConfig {
+            base_path: some("/tiles"),
+            ..Default::default()
+        };
+        let req =
+            TestRequest::with_uri("/tiles/style/basic").insert_header(("host", "example.org"));
+        assert_eq!(
+            get_base_url(&req.to_http_request(), &config).unwrap(),
+            "http://example.org/tiles"
+        );
+        let req = TestRequest::with_uri("/tiles/style/basic")
+            .insert_header(("host", "example.org"))
+            .insert_header(("x-rewrite-url", "/proxy/style/basic"));
+        assert_eq!(
+            get_base_url(&req.to_http_request(), &config).unwrap(),
+            "http://example.org/proxy"
+        );
+
+        let config = SrvConfig {
+            base_path: some("/tiles"),
+            public_url: some("https://maps.example.org/v1/"),
+            ..Default::default()
+        };
+        let req =
+            TestRequest::with_uri("/tiles/points?key=abc").insert_header(("host", "internal"));
+        let req = req.to_http_request();
+        assert_eq!(
+            get_base_url(&req, &config).unwrap(),
+            "https://maps.example.org/v1"
+        );
+        assert_eq!(
+            get_tiles_url(&req, &config, TileScheme::Xyz).unwrap(),
+            "https://maps.example.org/v1/points/{z}/{x}/{y}?key=abc"
+        );
+
+        let req = TestRequest::with_uri("/points?scheme=tms&key=abc")
+            .insert_header(("host", "example.org"))
+            .to_http_request();
+        let config = SrvConfig::default();
+        assert_eq!(
+            get_tiles_url(&req, &config, TileScheme::Tms).unwrap(),
+            "http://example.org/points/tms/{z}/{x}/{y}?key=abc"
+        );
+        let req = TestRequest::with_uri("/points?scheme=quadkey")
+            .insert_header(("host", "example.org"))
+            .to_http_request();
+        assert_eq!(
+            get_tiles_url(&req, &config, TileScheme::Quadkey).unwrap(),
+            "http://example.org/points/q/{quadkey}"
+        );
+    }
+
+    #[test]
+    fn test_normalize_base_path() {
+        assert_eq!(normalize_base_path(None), None);
+        assert_eq!(normalize_base_path(Some("/")), None);
+        assert_eq!(normalize_base_path(Some("tiles/")), some("/tiles"));
+        assert_eq!(normalize_base_path(Some("/a/b")), some("/a/b"));
+    }
+
+    #[test]
+    fn test_url_query() {
+        use actix_web::test::TestRequest;
+
+        use crate::srv::AuthConfig;
+
+        let query = |auth: Option<Auth>| {
+            let req = TestRequest::with_uri("/src/0/0/0?key=secret&token=abc&a=1")
+                .app_data(Data::new(auth))
+                .to_http_request();
+            get_url_query(&req).unwrap()
+        };
+        let config: AuthConfig = serde_yaml::from_str("keys: [{key: secret}]").unwrap();
+        assert_eq!(
+            query(Auth::new(Some(&config)).unwrap()),
+            UrlQuery::from([
+                ("token".to_string(), "abc".to_string()),
+                ("a".to_string(), "1".to_string()),
+            ])
+        );
+        // Without authentication, `key` is a regular function parameter
+        assert_eq!(
+            query(None),
+            UrlQuery::from([
+                ("key".to_string(), "secret".to_string()),
+                ("token".to_string(), "abc".to_string()),
+                ("a".to_string(), "1".to_string()),
+            ])
+        );
+        let req = TestRequest::default().to_http_request();
+        assert!(get_url_query(&req).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recompress() {
+        let data = Tile::from_static(b"some tile data, some tile data, some tile data");
+        let mvt = |encoding| TileInfo::new(Format::Mvt, encoding);
+        let same = recompress(data.clone(), mvt(Encoding::Gzip), Encoding::Gzip).unwrap();
+        assert_eq!(same.as_ptr(), data.as_ptr(), "tile must not be copied");
+        let encodings = [
+            Encoding::Uncompressed,
+            Encoding::Gzip,
+            Encoding::Zlib,
+            Encoding::Brotli,
+            Encoding::Zstd,
+        ];
+        for from in encodings {
+            let stored = recompress(data.clone(), mvt(Encoding::Uncompressed), from).unwrap();
+            if from != Encoding::Uncompressed {
+                assert_ne!(stored, data, "{from:?} tile must be compressed");
+            }
+            for to in encodings {
+                let tile = recompress(stored.clone(), mvt(from), to).unwrap();
+                let decoded = recompress(tile, mvt(to), Encoding::Uncompressed).unwrap();
+                assert_eq!(decoded, data, "{from:?} -> {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        use actix_web::http::header::ACCEPT_ENCODING;
+        use actix_web::test::TestRequest;
+
+        let negotiate = |encoding, accept: Option<&str>| {
+            let mut req = TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.insert_header((ACCEPT_ENCODING, accept));
+            }
+            let accept = req.to_http_request().get_header::<AcceptEncoding>();
+            negotiate_encoding(TileInfo::new(Format::Mvt, encoding), accept).unwrap()
+        };
+
+        assert_eq!(negotiate(Encoding::Zstd, Some("gzip")), Encoding::Gzip);
+        assert_eq!(
+            negotiate(Encoding::Zstd, Some("zstd, gzip")),
+            Encoding::Zstd
+        );
+        assert_eq!(negotiate(Encoding::Zstd, None), Encoding::Uncompressed);
+        assert_eq!(negotiate(Encoding::Zlib, Some("br")), Encoding::Brotli);
+        assert_eq!(negotiate(Encoding::Gzip, Some("deflate")), Encoding::Zlib);
+        assert_eq!(
+            negotiate(Encoding::Uncompressed, Some("zstd")),
+            Encoding::Zstd
+        );
+        assert_eq!(
+            negotiate(Encoding::Brotli, Some("identity")),
+            Encoding::Uncompressed
+        );
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let some = |v: &str| Some(v.to_string());
+        let mut config = SrvConfig::default();
+        assert_eq!(get_cache_control(&config, []), None);
+        assert_eq!(get_cache_control(&config, [None]), None);
+        assert_eq!(
+            get_cache_control(&config, [some("no-cache")]),
+            some("no-cache")
+        );
+
+        config.max_age = Some(60);
+        assert_eq!(get_cache_control(&config, []), some("public, max-age=60"));
+        assert_eq!(
+            get_cache_control(&config, [some("no-store"), some("no-store")]),
+            some("no-store")
+        );
+        // Sources without a value use the server-wide value, and the most restrictive value wins
+        assert_eq!(
+            get_cache_control(&config, [some("no-store"), None]),
+            some("no-store")
+        );
+        assert_eq!(
+            get_cache_control(&config, [None, some("public, max-age=3600")]),
+            some("public, max-age=60")
+        );
+        assert_eq!(
+            get_cache_control(&config, [some("public, max-age=30"), None]),
+            some("public, max-age=30")
+        );
+        assert_eq!(
+            get_cache_control(&config, [some("max-age=10"), some("no-cache")]),
+            some("no-cache")
+        );
+        assert_eq!(
+            get_cache_control(&config, [some("no-cache"), some("private, no-store")]),
+            some("private, no-store")
+        );
+        assert_eq!(
+            get_cache_control(&config, [some("public"), some("private, max-age=60")]),
+            some("private, max-age=60")
+        );
+        assert_eq!(
+            get_cache_control(
+                &config,
+                [some("public, max-age=60"), some("private, max-age=60")]
+            ),
+            some("private, max-age=60")
+        );
+
+        config.cache_control = some("private");
+        assert_eq!(get_cache_control(&config, [None]), some("private"));
+    }
+
+    #[test]
+    fn test_merge_tiles() {
+        let t1 = TileData::new(vec![1]).etag(Some("a".to_string()));
+        let t2 = TileData::new(vec![2, 3]).etag(Some("b".to_string()));
+        let tile = merge_tiles(&[t1.clone(), t2]);
+        assert_eq!(tile.data, vec![1, 2, 3]);
+        assert_eq!(tile.etag, Some("a-b".to_string()));
+        assert_eq!(tile.last_modified, None);
+
+        let tile = merge_tiles(&[t1, TileData::new(vec![4])]);
+        assert_eq!(tile.data, vec![1, 4]);
+        assert_eq!(tile.etag, None);
+    }
+
+    #[test]
+    fn test_merge_vector_tiles() {
+        let (layer1, layer2) = (b"layer one".to_vec(), b"layer two".to_vec());
+        let raw = || TileData::new(layer1.clone());
+        let gzip = || TileData::new(encode_gzip(&layer2).unwrap());
+        let brotli = || TileData::new(encode_brotli(&layer2).unwrap());
+        let expected = [layer1.clone(), layer2.clone()].concat();
+
+        // Same encodings that can be concatenated are kept as is
+        let (tile, enc) = merge_vector_tiles(vec![
+            (TileData::new(encode_gzip(&layer1).unwrap()), Encoding::Gzip),
+            (gzip(), Encoding::Gzip),
+            (TileData::default(), Encoding::Brotli),
+        ])
+        .unwrap();
+        assert_eq!(enc, Encoding::Gzip);
+        assert_eq!(decode_gzip(&tile.data).unwrap(), expected);
+
+        // Mixed encodings are decoded
+        for (part, encoding) in [(gzip(), Encoding::Gzip), (brotli(), Encoding::Brotli)] {
+            let (tile, enc) =
+                merge_vector_tiles(vec![(raw(), Encoding::Uncompressed), (part, encoding)])
+                    .unwrap();
+            assert_eq!(enc, Encoding::Uncompressed);
+            assert_eq!(tile.data, expected);
+        }
+
+        // Brotli tiles cannot be concatenated
+        let (tile, enc) = merge_vector_tiles(vec![
+            (
+                TileData::new(encode_brotli(&layer1).unwrap()),
+                Encoding::Brotli,
+            ),
+            (brotli(), Encoding::Brotli),
+        ])
+        .unwrap();
+        assert_eq!(enc, Encoding::Uncompressed);
+        assert_eq!(tile.data, expected);
+    }
+
+    #[test]
+    fn test_blend_raster_tiles() {
+        let png = |size: u32, pixel: [u8; 4]| {
+            let mut data = Cursor::new(Vec::new());
+            RgbaImage::from_pixel(size, size, image::Rgba(pixel))
+                .write_to(&mut data, ImageOutputFormat::Png)
+                .unwrap();
+            TileData::new(data.into_inner())
+        };
+        let decode = |tile: &TileData| image::load_from_memory(&tile.data).unwrap().into_rgba8();
+
+        // A half-transparent white tile over an opaque black one, scaled to the first tile size
+        let tile = blend_raster_tiles(
+            vec![
+                (png(2, [0, 0, 0, 255]), Format::Png),
+                (TileData::default(), Format::Webp),
+                (png(4, [255, 255, 255, 128]), Format::Png),
+            ],
+            Format::Png,
+        )
+        .unwrap();
+        let image = decode(&tile);
+        assert_eq!(image.dimensions(), (2, 2));
+        let [r, g, b, a] = image.get_pixel(1, 1).0;
+        assert_eq!((r, g, b), (128, 128, 128));
+        assert!(a >= 254);
+
+        // Fully transparent pixels keep the lower tile
+        let tile = blend_raster_tiles(
+            vec![
+                (png(2, [255, 0, 0, 255]), Format::Png),
+                (png(2, [0, 0, 255, 0]), Format::Png),
+            ],
+            Format::Jpeg,
+        )
+        .unwrap();
+        assert_eq!(&tile.data[..2], &[0xFF, 0xD8]);
+        let pixel = decode(&tile).get_pixel(0, 0).0;
+        assert!(pixel[0] > 240 && pixel[1] < 16 && pixel[2] < 16);
+
+        let tile = blend_raster_tiles(vec![(TileData::default(), Format::Png)], Format::Png);
+        assert!(tile.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_negotiate_raster_format() {
+        let accept = |v: &str| Some(Accept(v.split(',').map(|v| v.parse().unwrap()).collect()));
+        assert_eq!(negotiate_raster_format(Format::Jpeg, None), Format::Jpeg);
+        assert_eq!(negotiate_raster_format(Format::Webp, None), Format::Png);
+        assert_eq!(
+            negotiate_raster_format(Format::Png, accept("image/webp,image/jpeg,*/*;q=0.8")),
+            Format::Jpeg
+        );
+        assert_eq!(
+            negotiate_raster_format(Format::Jpeg, accept("image/webp,image/*;q=0.8")),
+            Format::Jpeg
+        );
+    }
+
     #[test]
     fn test_merge_tilejson() {
         let url = "http://localhost:8888/foo/{z}/{x}/{y}".to_string();
         let src1 = TestSource {
+            id: "test_source_1",
             tj: tilejson! {
                 tiles: vec![],
                 name: "layer1".to_string(),
@@ -527,6 +1976,7 @@ mod tests {
         );
 
         let src2 = TestSource {
+            id: "test_source_2",
             tj: tilejson! {
                 tiles: vec![],
                 name: "layer2".to_string(),
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::{stream, StreamExt};
+use serde::Serialize;
+
+use crate::config::AllSources;
+use crate::source::SourceKind;
+use crate::srv::AuthScope;
+
+/// The maximum time a single dependency check may take before it is reported as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum number of dependency checks that run concurrently
+const CHECK_CONCURRENCY: usize = 16;
+
+/// The time the server was started, used to report its uptime
+#[derive(Debug, Clone, Copy)]
+pub struct StartTime(pub Instant);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Error,
+}
+
+/// Detailed server status, returned by the `/status` endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerStatus {
+    pub status: HealthStatus,
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub pg_pools: Vec<PoolStatus>,
+    pub sources: BTreeMap<String, SourceStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolStatus {
+    pub id: String,
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub postgis_version: String,
+    pub max_size: usize,
+    pub size: usize,
+    pub available: usize,
+    pub waiting: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceStatus {
+    pub kind: SourceKind,
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ServerStatus {
+    /// All failed checks, one line each
+    #[must_use]
+    pub fn errors(&self) -> Vec<String> {
+        let pools = self
+            .pg_pools
+            .iter()
+            .filter_map(|v| Some(format!("{}: {}", v.id, v.error.as_ref()?)));
+        let sources = self
+            .sources
+            .iter()
+            .filter_map(|(id, v)| Some(format!("{id}: {}", v.error.as_ref()?)));
+        pools.chain(sources).collect()
+    }
+}
+
+/// Check the database connections and the tile sources the scope may access, running the checks
+/// concurrently. Each connection pool is checked once, and its result is reported for all of its
+/// sources. The pools are only reported if the scope may access all sources.
+pub async fn check_status(
+    sources: &AllSources,
+    uptime: Duration,
+    scope: &AuthScope,
+) -> ServerStatus {
+    let pg_pools = stream::iter(&sources.pg_pools)
+        .map(|pool| async move {
+            let (status, error) = run_check(pool.check()).await;
+            let pool_status = pool.status();
+            PoolStatus {
+                id: pool.get_id().to_string(),
+                status,
+                error,
+                postgis_version: pool.postgis_version().to_string(),
+                max_size: pool_status.max_size,
+                size: pool_status.size,
+                available: pool_status.available,
+                waiting: pool_status.waiting,
+            }
+        })
+        .buffered(CHECK_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    let pool_checks: &[PoolStatus] = &pg_pools;
+    let sources = stream::iter(sources.sources.iter())
+        .filter(|(id, _)| std::future::ready(scope.allows_source(id)))
+        .map(|(id, src)| async move {
+            let pool = src
+                .get_pool_id()
+                .and_then(|pool_id| pool_checks.iter().find(|v| v.id == pool_id));
+            let (status, error) = match pool {
+                Some(pool) => (pool.status, pool.error.clone()),
+                None => run_check(src.check_health()).await,
+            };
+            let kind = src.get_kind();
+            (
+                id.to_string(),
+                SourceStatus {
+                    kind,
+                    status,
+                    error,
+                },
+            )
+        })
+        .buffer_unordered(CHECK_CONCURRENCY)
+        .collect::<BTreeMap<_, _>>()
+        .await;
+
+    let pg_pools = if scope.sources.is_none() {
+        pg_pools
+    } else {
+        Vec::new()
+    };
+    let is_ok = pg_pools.iter().all(|v| v.status == HealthStatus::Ok)
+        && sources.values().all(|v| v.status == HealthStatus::Ok);
+    ServerStatus {
+        status: if is_ok {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Error
+        },
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_seconds: uptime.as_secs(),
+        pg_pools,
+        sources,
+    }
+}
+
+async fn run_check<E: Display>(
+    check: impl Future<Output = Result<(), E>>,
+) -> (HealthStatus, Option<String>) {
+    match actix_rt::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => (HealthStatus::Ok, None),
+        Ok(Err(e)) => (HealthStatus::Error, Some(e.to_string())),
+        Err(_) => (
+            HealthStatus::Error,
+            Some(format!(
+                "Check did not complete in {} seconds",
+                CHECK_TIMEOUT.as_secs()
+            )),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_run_check() {
+        assert_eq!(
+            run_check(async { Ok::<_, String>(()) }).await,
+            (HealthStatus::Ok, None)
+        );
+        assert_eq!(
+            run_check(async { Err("failed".to_string()) }).await,
+            (HealthStatus::Error, Some("failed".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_status_errors() {
+        let source = |error: Option<&str>| SourceStatus {
+            kind: SourceKind::Mbtiles,
+            status: if error.is_some() {
+                HealthStatus::Error
+            } else {
+                HealthStatus::Ok
+            },
+            error: error.map(ToString::to_string),
+        };
+        let status = ServerStatus {
+            status: HealthStatus::Error,
+            version: "0.0.0",
+            uptime_seconds: 10,
+            pg_pools: vec![],
+            sources: BTreeMap::from([
+                ("a".to_string(), source(None)),
+                ("b".to_string(), source(Some("missing file"))),
+            ]),
+        };
+        assert_eq!(status.errors(), vec!["b: missing file".to_string()]);
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(
+            json["sources"]["a"],
+            serde_json::json!({"kind": "mbtiles", "status": "ok"})
+        );
+        assert_eq!(json["sources"]["b"]["status"], "error");
+    }
+}
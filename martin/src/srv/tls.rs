@@ -0,0 +1,135 @@
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use log::info;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+
+use crate::Error::{TlsCertError, TlsConfigError};
+use crate::Result;
+
+/// Serves the certificate and private key loaded from PEM files to all TLS connections.
+/// The files can be re-read while the server is running, e.g. after the certificate was renewed,
+/// in which case the new connections use the new certificate.
+pub struct TlsCertResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    certified_key: ArcSwap<CertifiedKey>,
+}
+
+impl Debug for TlsCertResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsCertResolver")
+            .field("cert", &self.cert)
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsCertResolver {
+    /// Create a resolver if both certificate and private key files are configured
+    pub fn new(cert: Option<&PathBuf>, key: Option<&PathBuf>) -> Result<Option<Self>> {
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok(Some(Self {
+                certified_key: ArcSwap::from_pointee(load_certified_key(cert, key)?),
+                cert: cert.clone(),
+                key: key.clone(),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(TlsConfigError),
+        }
+    }
+
+    /// Re-read the certificate and private key files. On error, the current ones are kept.
+    pub fn reload(&self) -> Result<()> {
+        let certified_key = load_certified_key(&self.cert, &self.key)?;
+        self.certified_key.store(Arc::new(certified_key));
+        info!("Reloaded TLS certificate {}", self.cert.display());
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn server_config(self: Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self)
+    }
+}
+
+impl ResolvesServerCert for TlsCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.load_full())
+    }
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey> {
+    let on_err = |path: &Path, e: String| TlsCertError(e, path.to_path_buf());
+    let read_pem = |path: &Path| {
+        let file = File::open(path).map_err(|e| on_err(path, e.to_string()))?;
+        rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|e| on_err(path, e.to_string()))
+    };
+
+    let certs: Vec<_> = read_pem(cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(v) => Some(Certificate(v)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(on_err(cert, "no certificates found".to_string()));
+    }
+
+    let private_key = read_pem(key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(v) | Item::RSAKey(v) | Item::ECKey(v) => Some(PrivateKey(v)),
+            _ => None,
+        })
+        .ok_or_else(|| on_err(key, "no private key found".to_string()))?;
+    let signing_key = any_supported_type(&private_key).map_err(|e| on_err(key, e.to_string()))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "../tests/fixtures/tls/cert.pem";
+    const KEY: &str = "../tests/fixtures/tls/key.pem";
+
+    #[test]
+    fn load_tls_cert() {
+        let (cert, key) = (PathBuf::from(CERT), PathBuf::from(KEY));
+        assert!(TlsCertResolver::new(None, None).unwrap().is_none());
+        assert!(matches!(
+            TlsCertResolver::new(Some(&cert), None),
+            Err(TlsConfigError)
+        ));
+        assert!(matches!(
+            TlsCertResolver::new(Some(&key), Some(&key)),
+            Err(TlsCertError(..))
+        ));
+        assert!(matches!(
+            TlsCertResolver::new(Some(&cert), Some(&cert)),
+            Err(TlsCertError(..))
+        ));
+
+        let resolver = TlsCertResolver::new(Some(&cert), Some(&key))
+            .unwrap()
+            .unwrap();
+        let before = resolver.certified_key.load_full();
+        resolver.reload().unwrap();
+        let after = resolver.certified_key.load_full();
+        assert!(!Arc::ptr_eq(&before, &after));
+        assert_eq!(before.cert, after.cert);
+    }
+}
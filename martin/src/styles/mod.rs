@@ -0,0 +1,253 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::config::AllSources;
+use crate::file_config::{FileConfigEnum, FileError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum StyleError {
+    #[error("Style {0} not found")]
+    StyleNotFound(String),
+
+    #[error("IO error {0}: {}", .1.display())]
+    IoError(std::io::Error, PathBuf),
+
+    #[error("Unable to parse style {}: {0}", .1.display())]
+    StyleParsingError(serde_json::Error, PathBuf),
+}
+
+pub fn resolve_styles(config: &mut Option<FileConfigEnum>) -> Result<StyleSources, FileError> {
+    let Some(cfg) = config else {
+        return Ok(StyleSources::default());
+    };
+
+    let cfg = cfg.extract_file_config();
+    let mut results = StyleSources::default();
+    let mut paths = Vec::new();
+    let mut configs = HashMap::new();
+
+    if let Some(sources) = cfg.sources {
+        for (id, source) in sources {
+            configs.insert(id.clone(), source.clone());
+            let path = source.abs_path()?;
+            if !path.is_file() {
+                return Err(FileError::InvalidSourceFilePath(id, path));
+            }
+            add_source(id, path, &mut results);
+        }
+    }
+
+    if let Some(cfg_paths) = cfg.paths {
+        for path in cfg_paths {
+            if path.is_dir() {
+                let on_err = |e| FileError::IoError(e, path.clone());
+                for entry in path.read_dir().map_err(on_err)? {
+                    let file = entry.map_err(on_err)?.path();
+                    if file.is_file() && file.extension().map_or(false, |e| e == "json") {
+                        add_file(file, &mut results);
+                    }
+                }
+            } else if path.is_file() {
+                add_file(path.clone(), &mut results);
+            } else {
+                return Err(FileError::InvalidFilePath(path));
+            }
+            paths.push(path);
+        }
+    }
+
+    *config = FileConfigEnum::new_extended(paths, configs, cfg.unrecognized);
+
+    Ok(results)
+}
+
+/// Add a style file, using the file name without extension as its ID
+fn add_file(path: PathBuf, results: &mut StyleSources) {
+    let Some(id) = path.file_stem() else {
+        warn!("Ignoring style source with no name from {}", path.display());
+        return;
+    };
+    add_source(id.to_string_lossy().to_string(), path, results);
+}
+
+fn add_source(id: String, path: PathBuf, results: &mut StyleSources) {
+    let disp_path = path.display();
+    match results.0.entry(id) {
+        Entry::Occupied(v) => {
+            warn!("Ignoring duplicate style source {} from {disp_path} because it was already configured for {}",
+                v.key(), v.get().path.display());
+        }
+        Entry::Vacant(v) => {
+            info!("Configured style source {} from {disp_path}", v.key());
+            v.insert(StyleSource { path });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StyleSources(HashMap<String, StyleSource>);
+
+impl StyleSources {
+    pub fn get_style_source(&self, id: &str) -> Result<&StyleSource, StyleError> {
+        self.0
+            .get(id)
+            .ok_or_else(|| StyleError::StyleNotFound(id.to_string()))
+    }
+
+    /// Read the style with the given ID, and rewrite its references to the tile, sprite
+    /// and font sources of this server into absolute URLs starting with `base_url`.
+    /// The style file is read on every request, so the changes are reflected immediately.
+    pub async fn get_style(
+        &self,
+        id: &str,
+        sources: &AllSources,
+        base_url: &str,
+    ) -> Result<Value, StyleError> {
+        let path = &self.get_style_source(id)?.path;
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| StyleError::IoError(e, path.clone()))?;
+        let mut style = serde_json::from_slice(&data)
+            .map_err(|e| StyleError::StyleParsingError(e, path.clone()))?;
+        rewrite_style(
+            &mut style,
+            base_url,
+            |ids| sources.sources.get_sources(ids, None).is_ok(),
+            |ids| {
+                ids.split(',')
+                    .all(|id| sources.sprites.get_sprite_source(id).is_ok())
+            },
+        );
+        Ok(style)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StyleSource {
+    path: PathBuf,
+}
+
+/// Local references are the values without a URL scheme, e.g. `points` or `points,lines`
+fn local_ref(value: &Value) -> Option<&str> {
+    value
+        .as_str()
+        .filter(|v| !v.contains("://"))
+        .map(|v| v.trim_start_matches('/'))
+}
+
+/// Rewrite the local references in a style document:
+///  * `url` of the style sources with local tile source IDs into their tile metadata URLs
+///  * `sprite` with local sprite IDs into the sprite URLs
+///  * local `glyphs` into the font URL template of this server
+fn rewrite_style(
+    style: &mut Value,
+    base_url: &str,
+    is_source: impl Fn(&str) -> bool,
+    is_sprite: impl Fn(&str) -> bool,
+) {
+    if let Some(sources) = style.get_mut("sources").and_then(Value::as_object_mut) {
+        for source in sources.values_mut() {
+            if let Some(url) = source.get_mut("url") {
+                if let Some(ids) = local_ref(url).filter(|ids| is_source(ids)) {
+                    *url = Value::String(format!("{base_url}/{ids}"));
+                }
+            }
+        }
+    }
+
+    let rewrite_sprite = |url: &mut Value| {
+        if let Some(ids) = local_ref(url).filter(|ids| is_sprite(ids)) {
+            *url = Value::String(format!("{base_url}/sprite/{ids}"));
+        }
+    };
+    match style.get_mut("sprite") {
+        Some(Value::Array(sprites)) => {
+            for sprite in sprites {
+                if let Some(url) = sprite.get_mut("url") {
+                    rewrite_sprite(url);
+                }
+            }
+        }
+        Some(url) => rewrite_sprite(url),
+        None => {}
+    }
+
+    if let Some(glyphs) = style.get_mut("glyphs") {
+        if local_ref(glyphs).is_some() {
+            *glyphs = Value::String(format!("{base_url}/font/{{fontstack}}/{{range}}.pbf"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_styles() {
+        let mut cfg = FileConfigEnum::new(vec![PathBuf::from("../tests/fixtures/styles")]);
+        let styles = resolve_styles(&mut cfg).unwrap();
+        assert!(styles.get_style_source("maplibre_demo").is_ok());
+        assert!(matches!(
+            styles.get_style_source("missing"),
+            Err(StyleError::StyleNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_rewrite_style() {
+        let mut style = json!({
+            "version": 8,
+            "sources": {
+                "local": {"type": "vector", "url": "points,lines"},
+                "unknown": {"type": "vector", "url": "other"},
+                "remote": {"type": "vector", "url": "https://example.org/points"},
+                "tiles": {"type": "raster", "tiles": ["https://example.org/{z}/{x}/{y}.png"]},
+            },
+            "sprite": [
+                {"id": "default", "url": "/icons"},
+                {"id": "remote", "url": "https://example.org/sprite"},
+            ],
+            "glyphs": "fonts/{fontstack}/{range}.pbf",
+            "layers": [],
+        });
+        rewrite_style(
+            &mut style,
+            "http://localhost:3000",
+            |ids| ids == "points,lines",
+            |ids| ids == "icons",
+        );
+        assert_eq!(
+            style,
+            json!({
+                "version": 8,
+                "sources": {
+                    "local": {"type": "vector", "url": "http://localhost:3000/points,lines"},
+                    "unknown": {"type": "vector", "url": "other"},
+                    "remote": {"type": "vector", "url": "https://example.org/points"},
+                    "tiles": {"type": "raster", "tiles": ["https://example.org/{z}/{x}/{y}.png"]},
+                },
+                "sprite": [
+                    {"id": "default", "url": "http://localhost:3000/sprite/icons"},
+                    {"id": "remote", "url": "https://example.org/sprite"},
+                ],
+                "glyphs": "http://localhost:3000/font/{fontstack}/{range}.pbf",
+                "layers": [],
+            })
+        );
+
+        let mut style =
+            json!({"sprite": "icons", "glyphs": "https://example.org/{fontstack}/{range}"});
+        rewrite_style(&mut style, "http://host/tiles", |_| false, |_| true);
+        assert_eq!(
+            style,
+            json!({"sprite": "http://host/tiles/sprite/icons", "glyphs": "https://example.org/{fontstack}/{range}"})
+        );
+    }
+}
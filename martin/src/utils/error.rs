@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 use crate::file_config::FileError;
 use crate::pg::PgError;
+use crate::source::Xyz;
 use crate::sprites::SpriteError;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -15,6 +16,18 @@ pub enum Error {
     #[error("Unable to bind to {1}: {0}")]
     BindingError(io::Error, String),
 
+    #[error("Both tls_cert and tls_key must be set to enable HTTPS")]
+    TlsConfigError,
+
+    #[error("Unable to load TLS certificate or key {}: {0}", .1.display())]
+    TlsCertError(String, PathBuf),
+
+    #[error("Invalid CORS configuration: {0}")]
+    CorsConfigError(String),
+
+    #[error("Unable to load JWT key {}: {0}", .1.display())]
+    JwtKeyError(String, PathBuf),
+
     #[error("Unable to load config file {}: {0}", .1.display())]
     ConfigLoadError(io::Error, PathBuf),
 
@@ -24,6 +37,9 @@ pub enum Error {
     #[error("Unable to write config file {}: {0}", .1.display())]
     ConfigWriteError(io::Error, PathBuf),
 
+    #[error("Unable to serialize config: {0}")]
+    ConfigSerializeError(serde_yaml::Error),
+
     #[error("No tile sources found. Set sources by giving a database connection string on command line, env variable, or a config file.")]
     NoSources,
 
@@ -38,4 +54,13 @@ pub enum Error {
 
     #[error("{0}")]
     SpriteError(#[from] SpriteError),
+
+    #[error("Invalid composite source {0}: {1}")]
+    CompositeError(String, String),
+
+    #[error("Invalid fallback source {0}: {1}")]
+    FallbackError(String, String),
+
+    #[error("Unable to overzoom tile {1:#} of source {0}: {2}")]
+    OverzoomError(String, Xyz, String),
 }
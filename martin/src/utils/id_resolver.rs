@@ -41,6 +41,21 @@ impl IdResolver {
         new_name
     }
 
+    /// A copy of the resolver with its own resolved names, not shared with this resolver
+    #[must_use]
+    pub fn snapshot(&self) -> Self {
+        let names = self.names.lock().expect("IdResolver panicked").clone();
+        Self {
+            names: Arc::new(Mutex::new(names)),
+            reserved: self.reserved.clone(),
+        }
+    }
+
+    /// Forget a resolved name, e.g. after its source was removed, so that it can be reused
+    pub fn remove(&self, name: &str) {
+        self.names.lock().expect("IdResolver panicked").remove(name);
+    }
+
     #[must_use]
     fn resolve_int(&self, name: &str, unique_name: String) -> String {
         // Ensure name has no prohibited characters like spaces, commas, slashes, or non-unicode etc.
@@ -107,5 +122,14 @@ mod tests {
 
         assert_eq!(r.resolve("a b", "a b".to_string()), "a-b");
         assert_eq!(r.resolve("a b", "ab2".to_string()), "a-b.1");
+
+        r.remove("a");
+        assert_eq!(r.resolve("a", "c".to_string()), "a");
+        assert_eq!(r.resolve("a", "b".to_string()), "a.1");
+
+        let copy = r.snapshot();
+        assert_eq!(copy.resolve("d", "d".to_string()), "d");
+        assert_eq!(copy.resolve("a", "b".to_string()), "a.1");
+        assert_eq!(r.resolve("d", "e".to_string()), "d");
     }
 }
@@ -1,9 +1,11 @@
 mod error;
 mod id_resolver;
+mod mvt;
 mod one_or_many;
 mod utilities;
 
 pub use error::*;
 pub use id_resolver::IdResolver;
+pub use mvt::{overzoom_vector_tile, rename_layers, LayerFilter};
 pub use one_or_many::OneOrMany;
 pub use utilities::*;
@@ -0,0 +1,753 @@
+use std::collections::BTreeSet;
+use std::io;
+
+/// The `layers` field number of the vector tile `Tile` message
+const TILE_LAYERS: u64 = 3;
+/// The `name` field number of the vector tile `Layer` message
+const LAYER_NAME: u64 = 1;
+/// The `features` field number of the vector tile `Layer` message
+const LAYER_FEATURES: u64 = 2;
+/// The `extent` field number of the vector tile `Layer` message
+const LAYER_EXTENT: u64 = 5;
+/// The `type` field number of the vector tile `Feature` message
+const FEATURE_TYPE: u64 = 3;
+/// The `geometry` field number of the vector tile `Feature` message
+const FEATURE_GEOMETRY: u64 = 4;
+
+const GEOM_POINT: u64 = 1;
+const GEOM_LINESTRING: u64 = 2;
+const GEOM_POLYGON: u64 = 3;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+/// The layer extent if the layer does not set it
+const DEFAULT_EXTENT: u64 = 4096;
+/// Overzoomed geometries are clipped to the tile, extended by this fraction of its extent,
+/// so that lines and polygon outlines do not end at the tile edges.
+const CLIP_BUFFER: f64 = 1.0 / 64.0;
+
+/// MVT layers requested with the `layers` and `exclude_layers` query parameters,
+/// each a comma-separated list of layer names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LayerFilter {
+    include: Option<BTreeSet<String>>,
+    exclude: BTreeSet<String>,
+}
+
+impl LayerFilter {
+    /// Create a filter from the query parameter values, or `None` if neither is set
+    #[must_use]
+    pub fn new(layers: Option<&str>, exclude_layers: Option<&str>) -> Option<Self> {
+        if layers.is_none() && exclude_layers.is_none() {
+            return None;
+        }
+        let parse = |v: &str| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>()
+        };
+        Some(Self {
+            include: layers.map(parse),
+            exclude: exclude_layers.map(parse).unwrap_or_default(),
+        })
+    }
+
+    /// Check if the layer is requested
+    #[must_use]
+    pub fn contains(&self, layer: &str) -> bool {
+        self.include.as_ref().map_or(true, |v| v.contains(layer)) && !self.exclude.contains(layer)
+    }
+
+    /// A 64-bit FNV-1a hash of the filter, which unlike its [`Hash`] implementation is the same
+    /// in all runs and builds, e.g. to tell apart the `ETag`s of the differently filtered tiles
+    #[must_use]
+    pub fn stable_hash(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        // The lengths are hashed too, so that different filters never hash the same bytes
+        for set in [self.include.as_ref(), Some(&self.exclude)] {
+            write(&set.map_or(u64::MAX, |v| v.len() as u64).to_le_bytes());
+            for name in set.into_iter().flatten() {
+                write(&(name.len() as u64).to_le_bytes());
+                write(name.as_bytes());
+            }
+        }
+        hash
+    }
+
+    /// Remove the layers that were not requested from an uncompressed vector tile.
+    /// The remaining layers are kept as is, without decoding their features.
+    pub fn filter_tile(&self, tile: &[u8]) -> io::Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(tile.len());
+        let mut pos = 0;
+        while pos < tile.len() {
+            let start = pos;
+            let (field, value) = read_field(tile, &mut pos)?;
+            let keep = match value {
+                FieldValue::Bytes(layer) if field == TILE_LAYERS => {
+                    layer_name(layer)?.map_or(true, |name| self.contains(name))
+                }
+                _ => true,
+            };
+            if keep {
+                result.extend_from_slice(&tile[start..pos]);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Rename all layers of an uncompressed vector tile, keeping their features as is
+pub fn rename_layers(tile: &[u8], name: &str) -> io::Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(tile.len() + name.len());
+    let mut pos = 0;
+    while pos < tile.len() {
+        let start = pos;
+        match read_field(tile, &mut pos)? {
+            (TILE_LAYERS, FieldValue::Bytes(layer)) => {
+                let mut renamed = Vec::with_capacity(layer.len() + name.len());
+                let mut layer_pos = 0;
+                while layer_pos < layer.len() {
+                    let field_start = layer_pos;
+                    match read_field(layer, &mut layer_pos)? {
+                        (LAYER_NAME, FieldValue::Bytes(_)) => {
+                            write_bytes_field(&mut renamed, LAYER_NAME, name.as_bytes());
+                        }
+                        _ => renamed.extend_from_slice(&layer[field_start..layer_pos]),
+                    }
+                }
+                write_bytes_field(&mut result, TILE_LAYERS, &renamed);
+            }
+            _ => result.extend_from_slice(&tile[start..pos]),
+        }
+    }
+    Ok(result)
+}
+
+/// Create a tile above the source's maxzoom from its ancestor tile `dz` zoom levels up.
+/// The geometries of the uncompressed vector tile are scaled by `2^dz`, shifted so that
+/// the child tile at the `ox`,`oy` offset within the ancestor becomes the whole tile,
+/// and clipped to it. Features and layers left without geometries are removed.
+pub fn overzoom_vector_tile(tile: &[u8], dz: u8, ox: u32, oy: u32) -> io::Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(tile.len());
+    let mut pos = 0;
+    while pos < tile.len() {
+        let start = pos;
+        match read_field(tile, &mut pos)? {
+            (TILE_LAYERS, FieldValue::Bytes(layer)) => {
+                if let Some(layer) = overzoom_layer(layer, dz, ox, oy)? {
+                    write_bytes_field(&mut result, TILE_LAYERS, &layer);
+                }
+            }
+            _ => result.extend_from_slice(&tile[start..pos]),
+        }
+    }
+    Ok(result)
+}
+
+type Point = (f64, f64);
+/// A tile edge: the point coordinate it limits, its value, and whether it is the minimum
+type Edge = (fn(Point) -> f64, f64, bool);
+
+/// Maps the ancestor tile coordinates of a layer to the child tile, and clips them
+struct Transform {
+    scale: f64,
+    dx: f64,
+    dy: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Transform {
+    fn new(extent: u64, dz: u8, ox: u32, oy: u32) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let extent = extent as f64;
+        let buffer = extent * CLIP_BUFFER;
+        Self {
+            scale: 2_f64.powi(i32::from(dz)),
+            dx: f64::from(ox) * extent,
+            dy: f64::from(oy) * extent,
+            min: -buffer,
+            max: extent + buffer,
+        }
+    }
+
+    fn apply(&self, (x, y): (i64, i64)) -> Point {
+        #[allow(clippy::cast_precision_loss)]
+        let (x, y) = (x as f64, y as f64);
+        (x * self.scale - self.dx, y * self.scale - self.dy)
+    }
+
+    fn contains(&self, (x, y): Point) -> bool {
+        (self.min..=self.max).contains(&x) && (self.min..=self.max).contains(&y)
+    }
+}
+
+fn overzoom_layer(layer: &[u8], dz: u8, ox: u32, oy: u32) -> io::Result<Option<Vec<u8>>> {
+    let mut extent = DEFAULT_EXTENT;
+    let mut pos = 0;
+    while pos < layer.len() {
+        if let (LAYER_EXTENT, FieldValue::Varint(value)) = read_field(layer, &mut pos)? {
+            extent = value;
+        }
+    }
+    let transform = Transform::new(extent, dz, ox, oy);
+
+    let mut result = Vec::with_capacity(layer.len());
+    let mut has_features = false;
+    pos = 0;
+    while pos < layer.len() {
+        let start = pos;
+        match read_field(layer, &mut pos)? {
+            (LAYER_FEATURES, FieldValue::Bytes(feature)) => {
+                if let Some(feature) = overzoom_feature(feature, &transform)? {
+                    write_bytes_field(&mut result, LAYER_FEATURES, &feature);
+                    has_features = true;
+                }
+            }
+            _ => result.extend_from_slice(&layer[start..pos]),
+        }
+    }
+    Ok(has_features.then_some(result))
+}
+
+/// Transform the geometry of a feature, keeping its other fields as is.
+/// Returns `None` if nothing is left of the geometry.
+fn overzoom_feature(feature: &[u8], transform: &Transform) -> io::Result<Option<Vec<u8>>> {
+    let mut geom_type = 0;
+    let mut geometry = None;
+    let mut pos = 0;
+    while pos < feature.len() {
+        match read_field(feature, &mut pos)? {
+            (FEATURE_TYPE, FieldValue::Varint(value)) => geom_type = value,
+            (FEATURE_GEOMETRY, FieldValue::Bytes(value)) => geometry = Some(value),
+            _ => {}
+        }
+    }
+    let Some(geometry) = geometry else {
+        return Ok(None);
+    };
+
+    let parts = decode_geometry(geometry)?;
+    let parts = match geom_type {
+        GEOM_POINT => parts
+            .into_iter()
+            .flatten()
+            .map(|p| transform.apply(p))
+            .filter(|p| transform.contains(*p))
+            .map(|p| vec![round_point(p)])
+            .collect(),
+        GEOM_LINESTRING => parts
+            .iter()
+            .flat_map(|line| clip_line(line, transform))
+            .collect(),
+        GEOM_POLYGON => clip_polygon(&parts, transform),
+        // Geometries of unknown type cannot be transformed
+        _ => return Ok(None),
+    };
+    let Some(geometry) = encode_geometry(&parts, geom_type) else {
+        return Ok(None);
+    };
+
+    let mut result = Vec::with_capacity(feature.len());
+    pos = 0;
+    while pos < feature.len() {
+        let start = pos;
+        if read_field(feature, &mut pos)?.0 != FEATURE_GEOMETRY {
+            result.extend_from_slice(&feature[start..pos]);
+        }
+    }
+    write_bytes_field(&mut result, FEATURE_GEOMETRY, &geometry);
+    Ok(Some(result))
+}
+
+/// Decode the geometry commands into parts, each starting with a `MoveTo` command.
+/// Polygon rings are returned without repeating their first point.
+fn decode_geometry(geometry: &[u8]) -> io::Result<Vec<Vec<(i64, i64)>>> {
+    let mut parts: Vec<Vec<(i64, i64)>> = Vec::new();
+    let (mut x, mut y) = (0_i64, 0_i64);
+    let mut pos = 0;
+    let next = |pos: &mut usize| -> io::Result<u32> {
+        u32::try_from(read_varint(geometry, pos)?).map_err(invalid_data)
+    };
+    while pos < geometry.len() {
+        let command = next(&mut pos)?;
+        let (id, count) = (command & 0x7, command >> 3);
+        match id {
+            CMD_MOVE_TO | CMD_LINE_TO => {
+                for _ in 0..count {
+                    x += i64::from(zigzag_decode(next(&mut pos)?));
+                    y += i64::from(zigzag_decode(next(&mut pos)?));
+                    if id == CMD_MOVE_TO {
+                        parts.push(Vec::new());
+                    }
+                    parts
+                        .last_mut()
+                        .ok_or_else(|| invalid_data("Geometry does not start with MoveTo"))?
+                        .push((x, y));
+                }
+            }
+            CMD_CLOSE_PATH => {}
+            _ => Err(invalid_data(format!("Unknown geometry command {id}")))?,
+        }
+    }
+    Ok(parts)
+}
+
+/// Encode the geometry parts, or return `None` if there are none
+fn encode_geometry(parts: &[Vec<(i64, i64)>], geom_type: u64) -> Option<Vec<u8>> {
+    if parts.is_empty() {
+        return None;
+    }
+    let mut commands = Vec::new();
+    let mut cursor = (0_i64, 0_i64);
+    let mut move_to = |commands: &mut Vec<u32>, (x, y): (i64, i64)| {
+        commands.push(zigzag_encode(x - cursor.0));
+        commands.push(zigzag_encode(y - cursor.1));
+        cursor = (x, y);
+    };
+    if geom_type == GEOM_POINT {
+        commands.push(command(CMD_MOVE_TO, parts.len()));
+        for point in parts.iter().flatten() {
+            move_to(&mut commands, *point);
+        }
+    } else {
+        for part in parts {
+            commands.push(command(CMD_MOVE_TO, 1));
+            move_to(&mut commands, part[0]);
+            commands.push(command(CMD_LINE_TO, part.len() - 1));
+            for point in &part[1..] {
+                move_to(&mut commands, *point);
+            }
+            if geom_type == GEOM_POLYGON {
+                commands.push(command(CMD_CLOSE_PATH, 1));
+            }
+        }
+    }
+    let mut result = Vec::with_capacity(commands.len() * 2);
+    for value in commands {
+        write_varint(&mut result, u64::from(value));
+    }
+    Some(result)
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    id | (u32::try_from(count).unwrap_or(u32::MAX >> 3) << 3)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn round_point((x, y): Point) -> (i64, i64) {
+    (x.round() as i64, y.round() as i64)
+}
+
+/// Round the coordinates to integers, removing the repeated points
+fn round_points(points: &[Point]) -> Vec<(i64, i64)> {
+    let mut points: Vec<_> = points.iter().map(|p| round_point(*p)).collect();
+    points.dedup();
+    points
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn zigzag_encode(value: i64) -> u32 {
+    let value = value as i32;
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn zigzag_decode(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+/// Twice the signed area of a ring. Exterior rings have a positive area in tile coordinates.
+fn ring_area(ring: &[(i64, i64)]) -> i64 {
+    let mut area = 0;
+    for (i, (x1, y1)) in ring.iter().enumerate() {
+        let (x2, y2) = ring[(i + 1) % ring.len()];
+        area += x1 * y2 - x2 * y1;
+    }
+    area
+}
+
+/// Clip a line to the tile, splitting it into several lines where it leaves the tile
+fn clip_line(line: &[(i64, i64)], transform: &Transform) -> Vec<Vec<(i64, i64)>> {
+    let mut lines = Vec::new();
+    let mut current: Vec<Point> = Vec::new();
+    for segment in line.windows(2) {
+        let (a, b) = (transform.apply(segment[0]), transform.apply(segment[1]));
+        if let Some((start, end)) = clip_segment(a, b, transform) {
+            if current.is_empty() || start != a {
+                if current.len() > 1 {
+                    lines.push(std::mem::take(&mut current));
+                }
+                current = vec![start];
+            }
+            current.push(end);
+            if end != b {
+                lines.push(std::mem::take(&mut current));
+            }
+        } else if current.len() > 1 {
+            lines.push(std::mem::take(&mut current));
+        } else {
+            current.clear();
+        }
+    }
+    if current.len() > 1 {
+        lines.push(current);
+    }
+    lines
+        .iter()
+        .map(|line| round_points(line))
+        .filter(|line| line.len() > 1)
+        .collect()
+}
+
+/// Clip a segment to the tile using the Liang-Barsky algorithm
+fn clip_segment(a: Point, b: Point, transform: &Transform) -> Option<(Point, Point)> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    for (p, q) in [
+        (-dx, a.0 - transform.min),
+        (dx, transform.max - a.0),
+        (-dy, a.1 - transform.min),
+        (dy, transform.max - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| (a.0 + t * dx, a.1 + t * dy);
+    Some((
+        if t0 > 0.0 { at(t0) } else { a },
+        if t1 < 1.0 { at(t1) } else { b },
+    ))
+}
+
+/// Clip the polygon rings to the tile. The interior rings of an exterior ring
+/// that is clipped away entirely are removed as well.
+fn clip_polygon(rings: &[Vec<(i64, i64)>], transform: &Transform) -> Vec<Vec<(i64, i64)>> {
+    let mut result = Vec::new();
+    let mut keep_interior = false;
+    for ring in rings {
+        let area = ring_area(ring);
+        if area == 0 || (area < 0 && !keep_interior) {
+            continue;
+        }
+        let points: Vec<_> = ring.iter().map(|p| transform.apply(*p)).collect();
+        let mut clipped = round_points(&clip_ring(points, transform));
+        if clipped.len() > 1 && clipped.first() == clipped.last() {
+            clipped.pop();
+        }
+        let is_kept = clipped.len() > 2 && ring_area(&clipped) != 0;
+        if area > 0 {
+            keep_interior = is_kept;
+        }
+        if is_kept {
+            result.push(clipped);
+        }
+    }
+    result
+}
+
+/// Clip a ring to the tile using the Sutherland-Hodgman algorithm
+fn clip_ring(mut ring: Vec<Point>, transform: &Transform) -> Vec<Point> {
+    let (min, max) = (transform.min, transform.max);
+    let edges: [Edge; 4] = [
+        (|p| p.0, min, true),
+        (|p| p.0, max, false),
+        (|p| p.1, min, true),
+        (|p| p.1, max, false),
+    ];
+    for (coord, limit, is_min) in edges {
+        let inside = |p: Point| {
+            if is_min {
+                coord(p) >= limit
+            } else {
+                coord(p) <= limit
+            }
+        };
+        let input = std::mem::take(&mut ring);
+        for (i, &current) in input.iter().enumerate() {
+            let prev = input[(i + input.len() - 1) % input.len()];
+            if inside(current) {
+                if !inside(prev) {
+                    ring.push(intersect(prev, current, coord, limit));
+                }
+                ring.push(current);
+            } else if inside(prev) {
+                ring.push(intersect(prev, current, coord, limit));
+            }
+        }
+    }
+    ring
+}
+
+/// The point where the segment crosses the line where the coordinate equals the limit
+fn intersect(a: Point, b: Point, coord: fn(Point) -> f64, limit: f64) -> Point {
+    let t = (limit - coord(a)) / (coord(b) - coord(a));
+    (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1))
+}
+
+/// Find the name of an encoded vector tile layer
+fn layer_name(layer: &[u8]) -> io::Result<Option<&str>> {
+    let mut pos = 0;
+    while pos < layer.len() {
+        if let (LAYER_NAME, FieldValue::Bytes(name)) = read_field(layer, &mut pos)? {
+            return std::str::from_utf8(name).map(Some).map_err(invalid_data);
+        }
+    }
+    Ok(None)
+}
+
+/// The value of a protobuf field
+enum FieldValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Read a protobuf field at the position, advancing it past the field.
+/// Returns the field number and its value. Fixed-size values are not decoded.
+fn read_field<'a>(data: &'a [u8], pos: &mut usize) -> io::Result<(u64, FieldValue<'a>)> {
+    let key = read_varint(data, pos)?;
+    let len = match key & 0x7 {
+        0 => return Ok((key >> 3, FieldValue::Varint(read_varint(data, pos)?))),
+        1 => 8,
+        2 => usize::try_from(read_varint(data, pos)?).map_err(invalid_data)?,
+        5 => 4,
+        wire_type => Err(invalid_data(format!(
+            "Unsupported protobuf wire type {wire_type}"
+        )))?,
+    };
+    let end = pos
+        .checked_add(len)
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| invalid_data("Truncated protobuf field"))?;
+    let value = &data[*pos..end];
+    *pos = end;
+    Ok((
+        key >> 3,
+        if key & 0x7 == 2 {
+            FieldValue::Bytes(value)
+        } else {
+            FieldValue::Fixed
+        },
+    ))
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| invalid_data("Truncated protobuf varint"))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("Invalid protobuf varint"))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    write_varint(out, field << 3 | 2);
+    write_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a minimal vector tile layer with a name and a version field
+    fn layer(name: &str) -> Vec<u8> {
+        let mut layer = vec![0x0A, u8::try_from(name.len()).unwrap()];
+        layer.extend_from_slice(name.as_bytes());
+        layer.extend_from_slice(&[0x78, 0x02]);
+        let mut tile = vec![0x1A, u8::try_from(layer.len()).unwrap()];
+        tile.extend(layer);
+        tile
+    }
+
+    #[test]
+    fn test_layer_filter() {
+        assert_eq!(LayerFilter::new(None, None), None);
+        let filter = LayerFilter::new(Some("roads, water,"), Some("water")).unwrap();
+        assert!(filter.contains("roads"));
+        assert!(!filter.contains("water"));
+        assert!(!filter.contains("buildings"));
+        let filter = LayerFilter::new(None, Some("water")).unwrap();
+        assert!(filter.contains("roads"));
+        assert!(!filter.contains("water"));
+    }
+
+    #[test]
+    fn test_layer_filter_hash() {
+        let hash = |layers, exclude_layers| {
+            LayerFilter::new(layers, exclude_layers)
+                .unwrap()
+                .stable_hash()
+        };
+        assert_eq!(
+            hash(Some("roads,water"), None),
+            hash(Some("water,roads"), None)
+        );
+        assert_ne!(hash(Some("roads,water"), None), hash(Some("roads"), None));
+        assert_ne!(hash(Some("water"), None), hash(None, Some("water")));
+        assert_ne!(hash(Some(""), None), hash(None, Some("")));
+        // The hash must not change between versions, so that the clients' cached tiles stay valid
+        assert_eq!(hash(Some("water"), None), 9_285_168_206_550_152_332);
+    }
+
+    #[test]
+    fn test_filter_tile() {
+        let tile = [layer("roads"), layer("water"), layer("buildings")].concat();
+        let filter = LayerFilter::new(Some("roads,buildings"), None).unwrap();
+        assert_eq!(
+            filter.filter_tile(&tile).unwrap(),
+            [layer("roads"), layer("buildings")].concat()
+        );
+        let filter = LayerFilter::new(None, Some("roads,buildings")).unwrap();
+        assert_eq!(filter.filter_tile(&tile).unwrap(), layer("water"));
+        let filter = LayerFilter::new(Some("other"), None).unwrap();
+        assert!(filter.filter_tile(&tile).unwrap().is_empty());
+        assert!(filter.filter_tile(&tile[..tile.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_rename_layers() {
+        let tile = [layer("roads"), layer("water")].concat();
+        assert_eq!(
+            rename_layers(&tile, "lines").unwrap(),
+            [layer("lines"), layer("lines")].concat()
+        );
+        assert!(rename_layers(&tile[..tile.len() - 1], "lines").is_err());
+    }
+
+    type Parts = Vec<Vec<(i64, i64)>>;
+
+    /// Encode a vector tile with a single layer of the given features, using the default extent
+    fn features_tile(features: &[(u64, Parts)]) -> Vec<u8> {
+        let mut layer = vec![0x0A, 0x01, b'a', 0x78, 0x02];
+        for (geom_type, parts) in features {
+            let mut feature = Vec::new();
+            write_varint(&mut feature, FEATURE_TYPE << 3);
+            write_varint(&mut feature, *geom_type);
+            let geometry = encode_geometry(parts, *geom_type).unwrap();
+            write_bytes_field(&mut feature, FEATURE_GEOMETRY, &geometry);
+            write_bytes_field(&mut layer, LAYER_FEATURES, &feature);
+        }
+        let mut tile = Vec::new();
+        write_bytes_field(&mut tile, TILE_LAYERS, &layer);
+        tile
+    }
+
+    /// Decode the geometries of the features of the first layer
+    fn tile_features(tile: &[u8]) -> Vec<(u64, Parts)> {
+        let mut pos = 0;
+        let FieldValue::Bytes(layer) = read_field(tile, &mut pos).unwrap().1 else {
+            panic!("expected a layer");
+        };
+        let mut features = Vec::new();
+        pos = 0;
+        while pos < layer.len() {
+            if let (LAYER_FEATURES, FieldValue::Bytes(feature)) =
+                read_field(layer, &mut pos).unwrap()
+            {
+                let (mut geom_type, mut parts) = (0, Vec::new());
+                let mut pos = 0;
+                while pos < feature.len() {
+                    match read_field(feature, &mut pos).unwrap() {
+                        (FEATURE_TYPE, FieldValue::Varint(v)) => geom_type = v,
+                        (FEATURE_GEOMETRY, FieldValue::Bytes(v)) => {
+                            parts = decode_geometry(v).unwrap();
+                        }
+                        _ => {}
+                    }
+                }
+                features.push((geom_type, parts));
+            }
+        }
+        features
+    }
+
+    #[test]
+    fn test_overzoom_vector_tile() {
+        let square = |min: i64, max: i64| vec![(min, min), (max, min), (max, max), (min, max)];
+        let mut hole = square(1000, 2000);
+        hole.reverse();
+        let tile = features_tile(&[
+            (GEOM_POINT, vec![vec![(1000, 1000)], vec![(3000, 3000)]]),
+            (
+                GEOM_LINESTRING,
+                vec![vec![(0, 1000), (4096, 1000), (4096, 3000)]],
+            ),
+            (GEOM_POLYGON, vec![square(0, 4096), hole.clone()]),
+            // Outside of the child tile
+            (GEOM_POLYGON, vec![square(3000, 4000)]),
+        ]);
+
+        let child = overzoom_vector_tile(&tile, 1, 0, 0).unwrap();
+        let mut hole = square(2000, 4000);
+        hole.reverse();
+        assert_eq!(
+            tile_features(&child),
+            vec![
+                (GEOM_POINT, vec![vec![(2000, 2000)]]),
+                (GEOM_LINESTRING, vec![vec![(0, 2000), (4160, 2000)]]),
+                (
+                    GEOM_POLYGON,
+                    vec![vec![(0, 4160), (0, 0), (4160, 0), (4160, 4160)], hole]
+                ),
+            ]
+        );
+
+        // The line leaves and re-enters the child tile
+        let tile = features_tile(&[(
+            GEOM_LINESTRING,
+            vec![vec![(3000, 1000), (3000, 3000), (1000, 3000)]],
+        )]);
+        let child = overzoom_vector_tile(&tile, 1, 1, 0).unwrap();
+        assert_eq!(
+            tile_features(&child),
+            vec![(GEOM_LINESTRING, vec![vec![(1904, 2000), (1904, 4160)]])]
+        );
+
+        // Nothing is left of the layer
+        let tile = features_tile(&[(GEOM_POINT, vec![vec![(100, 100)]])]);
+        assert!(overzoom_vector_tile(&tile, 2, 3, 3).unwrap().is_empty());
+    }
+}
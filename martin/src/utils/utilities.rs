@@ -1,9 +1,14 @@
 use std::collections::{BTreeMap, HashMap};
+use std::io;
 use std::io::{Read as _, Write as _};
 
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use serde::{Deserialize, Serialize, Serializer};
+use actix_web::http::header::HeaderValue;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use martin_tile_utils::{Encoding, TileInfo};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::source::Tile;
 
 #[must_use]
 pub fn is_valid_zoom(zoom: u8, minzoom: Option<u8>, maxzoom: Option<u8>) -> bool {
@@ -11,6 +16,69 @@ pub fn is_valid_zoom(zoom: u8, minzoom: Option<u8>, maxzoom: Option<u8>) -> bool
         && maxzoom.map_or(true, |maxzoom| zoom <= maxzoom)
 }
 
+/// Build the `Cache-Control` header value from the `cache_control` and `max_age` settings.
+/// The `cache_control` value is used as is, and takes precedence over `max_age`.
+#[must_use]
+pub fn cache_control_value(cache_control: Option<&str>, max_age: Option<u64>) -> Option<String> {
+    match (cache_control, max_age) {
+        (Some(value), _) => Some(value.to_string()),
+        (None, Some(max_age)) => Some(format!("public, max-age={max_age}")),
+        (None, None) => None,
+    }
+}
+
+/// The most restrictive of the `Cache-Control` header values: `no-store`, then `no-cache`,
+/// then the shortest `max-age`. A missing value is the least restrictive one.
+#[must_use]
+pub fn most_restrictive_cache_control(
+    values: impl IntoIterator<Item = Option<String>>,
+) -> Option<String> {
+    values
+        .into_iter()
+        .min_by_key(|v| v.as_deref().map_or((4, 0, true), cache_control_rank))
+        .flatten()
+}
+
+/// The sort key of a `Cache-Control` value, lower for the values allowing less caching.
+/// The values without `no-store`, `no-cache` or `max-age` directives come after the others,
+/// and `private` values come before `public` ones with the same `max-age`.
+fn cache_control_rank(value: &str) -> (u8, u64, bool) {
+    let directives: Vec<_> = value
+        .split(',')
+        .map(|v| v.trim().to_ascii_lowercase())
+        .collect();
+    let has = |name: &str| directives.iter().any(|v| v == name);
+    let is_public = !has("private");
+    if has("no-store") {
+        (0, 0, is_public)
+    } else if has("no-cache") {
+        (1, 0, is_public)
+    } else if let Some(max_age) = directives
+        .iter()
+        .filter_map(|v| v.strip_prefix("max-age="))
+        .find_map(|v| v.trim_matches('"').parse().ok())
+    {
+        (2, max_age, is_public)
+    } else {
+        (3, 0, is_public)
+    }
+}
+
+/// A serde helper to reject the `cache_control` values that cannot be sent as a header value.
+pub fn deserialize_cache_control<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    if let Some(value) = &value {
+        if value.trim().is_empty() || HeaderValue::from_str(value).is_err() {
+            return Err(serde::de::Error::custom(format!(
+                "cache_control is not a valid header value: {value:?}"
+            )));
+        }
+    }
+    Ok(value)
+}
+
 /// A serde helper to store a boolean as an object.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -33,8 +101,9 @@ pub fn sorted_btree_map<K: Serialize + Ord, V>(value: &HashMap<K, V>) -> BTreeMa
     BTreeMap::from_iter(items)
 }
 
+/// Decode gzip data, including the concatenated gzip streams of the composite tiles
 pub fn decode_gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
-    let mut decoder = GzDecoder::new(data);
+    let mut decoder = MultiGzDecoder::new(data);
     let mut decompressed = Vec::new();
     decoder.read_to_end(&mut decompressed)?;
     Ok(decompressed)
@@ -46,6 +115,27 @@ pub fn encode_gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
     encoder.finish()
 }
 
+pub fn decode_zlib(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+pub fn encode_zlib(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+pub fn decode_zstd(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    zstd::decode_all(data)
+}
+
+pub fn encode_zstd(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+}
+
 pub fn decode_brotli(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
     let mut decoder = brotli::Decompressor::new(data, 4096);
     let mut decompressed = Vec::new();
@@ -58,3 +148,24 @@ pub fn encode_brotli(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
     encoder.write_all(data)?;
     Ok(encoder.into_inner())
 }
+
+/// Change the encoding of the tile data, returning it as is if the encoding is the same
+pub fn recompress(tile: Tile, info: TileInfo, encoding: Encoding) -> io::Result<Tile> {
+    if info.encoding == encoding {
+        return Ok(tile);
+    }
+    let tile = match info.encoding {
+        Encoding::Gzip => decode_gzip(&tile)?.into(),
+        Encoding::Zlib => decode_zlib(&tile)?.into(),
+        Encoding::Brotli => decode_brotli(&tile)?.into(),
+        Encoding::Zstd => decode_zstd(&tile)?.into(),
+        _ => tile,
+    };
+    Ok(match encoding {
+        Encoding::Gzip => encode_gzip(&tile)?.into(),
+        Encoding::Zlib => encode_zlib(&tile)?.into(),
+        Encoding::Brotli => encode_brotli(&tile)?.into(),
+        Encoding::Zstd => encode_zstd(&tile)?.into(),
+        _ => tile,
+    })
+}